@@ -0,0 +1,159 @@
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use clap::Args;
+use itertools::Itertools;
+
+use crate::{
+    commands::SubcommandDelegate,
+    config::{ConfigLoader, FtpConfig, FtpCreds},
+    ftp::{FtpStreamExt, RemoteEntry},
+    tracking::{
+        FileState, FilesTracking, GitFtpLog, RemoteTracking, SharedTracking, TrackingFileLoder,
+        collect_files,
+    },
+};
+
+#[derive(Args)]
+pub struct AdoptCommand {
+    /// Directory to adopt the remote deployment for
+    #[arg(short, long)]
+    path: Option<PathBuf>,
+
+    /// Number of threads to use for walking files
+    #[arg(short, long)]
+    jobs: Option<usize>,
+
+    /// Take over a site deployed with git-ftp, tracking the files of the commit in its
+    /// `.git-ftp.log` without comparing them
+    #[arg(long)]
+    git_ftp: bool,
+}
+
+impl SubcommandDelegate for AdoptCommand {
+    fn run(self) -> Result<(), Box<dyn std::error::Error>> {
+        let base_path = self.path.unwrap_or_else(|| PathBuf::from("."));
+
+        let config = FtpConfig::load_or_create(&base_path)?;
+        let creds = FtpCreds::load_or_create(&base_path)?;
+        let mut files_tracking = FilesTracking::load_or_create(&base_path)?;
+        let mut remote_tracking = RemoteTracking::load_or_create(&base_path)?;
+        let mut shared = if config.shared_tracking() {
+            Some(SharedTracking::load_into(
+                &creds,
+                &mut files_tracking,
+                &mut remote_tracking,
+            )?)
+        } else {
+            None
+        };
+
+        if self.git_ftp {
+            let log = GitFtpLog::new(&creds);
+            let commit = log
+                .read(&creds)?
+                .ok_or_else(|| format!("no git-ftp log found at {}", log.path()))?;
+            println!(
+                "[ftp-deploy] Adopting the files of commit {} deployed by git-ftp",
+                commit
+            );
+
+            let seeded = GitFtpLog::tracking(&base_path, &commit)?;
+            let adopted = seeded
+                .files
+                .values()
+                .filter(|state| **state != FileState::Directory)
+                .count();
+            files_tracking.files.extend(seeded.files);
+            files_tracking.write(&base_path)?;
+
+            if let Some(shared) = &mut shared {
+                shared.store(&creds, &files_tracking, &remote_tracking)?;
+            }
+
+            println!(
+                "[ftp-deploy] {} file(s) adopted, the next deploy uploads the changes since {}",
+                adopted, commit
+            );
+            return Ok(());
+        }
+
+        let files = collect_files(
+            &base_path,
+            FilesTracking::default(),
+            self.jobs.unwrap_or_else(num_cpus::get),
+            false,
+            None,
+        )?;
+
+        let root = creds.remote_path(".");
+        println!(
+            "[ftp-deploy] Comparing {} local file(s) with {}{}",
+            files.len(),
+            creds.server,
+            root
+        );
+
+        let mut ftp_stream = creds.open_stream()?;
+        let remote: HashMap<String, RemoteEntry> = ftp_stream
+            .list_recursive(&root)?
+            .into_iter()
+            .map(|entry| (entry.path.clone(), entry))
+            .collect();
+
+        let (mut adopted, mut different, mut missing) = (0, 0, 0);
+
+        for (path, (state, _)) in files.iter().sorted_by_key(|(path, _)| *path) {
+            let remote_path = creds.remote_path(path);
+
+            let identical = match (state, remote.get(&remote_path)) {
+                _ if remote_path == root => true,
+                (_, None) => {
+                    // whatever was tracked for it before is not on the server
+                    files_tracking.files.remove(path);
+                    remote_tracking.files.remove(path);
+                    missing += 1;
+                    continue;
+                }
+                (FileState::Directory, Some(entry)) => entry.is_dir,
+                (FileState::File(_), Some(entry)) if entry.is_dir => false,
+                (FileState::File(hash), Some(entry)) => {
+                    let local_size = fs::metadata(path)?.len() as usize;
+
+                    entry.size.is_none_or(|size| size == local_size)
+                        && ftp_stream.remote_sha256(&remote_path)? == *hash
+                }
+            };
+
+            if !identical {
+                println!("\tdifferent:\t{}", path.display());
+                files_tracking.files.remove(path);
+                remote_tracking.files.remove(path);
+                different += 1;
+                continue;
+            }
+
+            if let FileState::File(_) = state
+                && let Some(remote_state) = ftp_stream.remote_state(&remote_path)?
+            {
+                remote_tracking.files.insert(path.clone(), remote_state);
+            }
+
+            files_tracking.files.insert(path.clone(), state.clone());
+            adopted += 1;
+        }
+
+        files_tracking.write(&base_path)?;
+        remote_tracking.write(&base_path)?;
+
+        if let Some(shared) = &mut shared {
+            shared.store(&creds, &files_tracking, &remote_tracking)?;
+        }
+
+        println!(
+            "[ftp-deploy] {} file(s) adopted, {} file(s) differ, {} file(s) missing on the server",
+            adopted, different, missing
+        );
+
+        Ok(())
+    }
+}
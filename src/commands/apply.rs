@@ -0,0 +1,32 @@
+use std::path::PathBuf;
+
+use clap::Args;
+
+use crate::{
+    commands::{DeployCommand, SubcommandDelegate},
+    tracking::Plan,
+};
+
+#[derive(Args)]
+pub struct ApplyCommand {
+    /// Plan file written by `deploy --plan-out`
+    plan: PathBuf,
+
+    /// Directory the plan was made for
+    #[arg(short, long)]
+    path: Option<PathBuf>,
+}
+
+impl SubcommandDelegate for ApplyCommand {
+    fn run(self) -> Result<(), Box<dyn std::error::Error>> {
+        let plan = Plan::read(&self.plan)?;
+
+        println!(
+            "[ftp-deploy] Applying plan of {} change(s) made at {}",
+            plan.updates.len(),
+            plan.created
+        );
+
+        DeployCommand::for_plan(self.path, plan).run()
+    }
+}
@@ -0,0 +1,174 @@
+use std::{
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
+
+use clap::{Args, Subcommand};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    commands::SubcommandDelegate,
+    config::{BackupMode, ConfigLoader, FtpConfig, FtpCreds},
+    ftp::FtpStreamExt,
+    lock::LockGuard,
+    tracking::{
+        BackupSet, BackupsTracking, FileState, FilesTracking, RemoteTracking, SharedTracking,
+        TrackingFileLoder,
+    },
+};
+
+#[derive(Subcommand)]
+enum BackupsAction {
+    /// List all backups
+    List,
+
+    /// Put the backed up files back in place on the remote server
+    Restore {
+        /// Id of the backup to restore
+        id: String,
+    },
+}
+
+#[derive(Args)]
+pub struct BackupsCommand {
+    /// Directory to manage the backups of
+    #[arg(short, long, global = true)]
+    path: Option<PathBuf>,
+
+    #[command(subcommand)]
+    action: BackupsAction,
+}
+
+impl BackupsCommand {
+    fn restore(&self, base_path: &Path, id: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let config = FtpConfig::load_or_create(base_path)?;
+        let creds = FtpCreds::load_or_create(base_path)?;
+        let _lock = LockGuard::acquire(base_path, &creds, config.lock_ttl())?;
+
+        let mut backups_tracking = BackupsTracking::load(base_path)?;
+        let mut files_tracking = FilesTracking::load_or_create(base_path)?;
+        let mut remote_tracking = RemoteTracking::load_or_create(base_path)?;
+        let mut shared = if config.shared_tracking() {
+            Some(SharedTracking::load_into(
+                &creds,
+                &mut files_tracking,
+                &mut remote_tracking,
+            )?)
+        } else {
+            None
+        };
+
+        let set = backups_tracking
+            .get(id)
+            .cloned()
+            .ok_or_else(|| format!("unknown backup '{}'", id))?;
+
+        println!(
+            "[ftp-deploy] Restoring {} file(s) from backup {}",
+            set.files.len(),
+            id
+        );
+
+        let mut ftp_stream = creds.open_stream()?;
+        let mut restored = 0;
+
+        for file in &set.files {
+            let relative = creds.relative_remote_path(file);
+            let remote_path = creds.remote_path(file);
+
+            ftp_stream.cwd_or_create_recursive(Path::new(&remote_path).parent())?;
+
+            let res = match set.mode {
+                BackupMode::Remote => {
+                    let source = format!("{}/{}", BackupSet::remote_dir(&creds, id), relative);
+
+                    ftp_stream
+                        .rename_over(&source, &remote_path)
+                        .map_err(Into::into)
+                        .and_then(|_| ftp_stream.remote_sha256(&remote_path).map_err(Into::into))
+                }
+                BackupMode::Local => {
+                    let source = BackupSet::local_dir(base_path, id).join(&relative);
+
+                    File::open(&source)
+                        .map_err(Into::into)
+                        .and_then(|mut reader| {
+                            ftp_stream
+                                .put(&remote_path, &mut reader)
+                                .map_err(Into::into)
+                        })
+                        .and_then(|_| {
+                            let mut hasher = Sha256::new();
+                            io::copy(&mut File::open(&source)?, &mut hasher)?;
+                            Ok(format!("{:x}", hasher.finalize()))
+                        })
+                }
+                BackupMode::None => continue,
+            };
+
+            match res {
+                Ok(hash) => {
+                    // the tracking information must reflect what is on the server now
+                    files_tracking
+                        .files
+                        .insert(file.clone(), FileState::File(hash));
+                    match ftp_stream.remote_state(&remote_path)? {
+                        Some(state) => remote_tracking.files.insert(file.clone(), state),
+                        None => remote_tracking.files.remove(file),
+                    };
+                    restored += 1;
+                }
+                Err::<_, Box<dyn std::error::Error>>(err) => println!(
+                    "[ftp-deploy] Failed to restore file '{}': {}",
+                    file.display(),
+                    err
+                ),
+            }
+        }
+
+        files_tracking.write(base_path)?;
+        remote_tracking.write(base_path)?;
+
+        if let Some(shared) = &mut shared {
+            shared.store(&creds, &files_tracking, &remote_tracking)?;
+        }
+
+        // remote backups were moved back into place, so the backup itself is used up
+        if set.mode == BackupMode::Remote && restored == set.files.len() {
+            backups_tracking.remove(id, base_path, &creds)?;
+            backups_tracking.write(base_path)?;
+        }
+
+        println!(
+            "[ftp-deploy] Restored {} of {} file(s).",
+            restored,
+            set.files.len()
+        );
+
+        Ok(())
+    }
+}
+
+impl SubcommandDelegate for BackupsCommand {
+    fn run(self) -> Result<(), Box<dyn std::error::Error>> {
+        let base_path = self.path.clone().unwrap_or_else(|| PathBuf::from("."));
+
+        match &self.action {
+            BackupsAction::List => {
+                let backups_tracking = BackupsTracking::load(&base_path)?;
+
+                if backups_tracking.backups.is_empty() {
+                    println!("[ftp-deploy] No backups.");
+                }
+
+                for set in &backups_tracking.backups {
+                    println!("{}\t{:?}\t{} file(s)", set.id, set.mode, set.files.len());
+                }
+
+                Ok(())
+            }
+            BackupsAction::Restore { id } => self.restore(&base_path, id),
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use clap::Args;
+use itertools::Itertools;
+
+use crate::{
+    commands::SubcommandDelegate,
+    config::{ConfigLoader, FtpConfig, FtpCreds},
+    ftp::FtpStreamExt,
+    lock::LockGuard,
+    prompt::confirm,
+    tracking::{
+        FilesTracking, GIT_FTP_LOG, PathMatcher, ReleasesTracking, RemoteTracking, SharedTracking,
+        TrackingFileLoder,
+    },
+};
+
+#[derive(Args)]
+pub struct CleanCommand {
+    /// Directory to clean up the deployment of
+    #[arg(short, long)]
+    path: Option<PathBuf>,
+
+    /// Delete without asking for confirmation
+    #[arg(short, long)]
+    yes: bool,
+}
+
+impl SubcommandDelegate for CleanCommand {
+    fn run(self) -> Result<(), Box<dyn std::error::Error>> {
+        let base_path = self.path.unwrap_or_else(|| PathBuf::from("."));
+
+        let config = FtpConfig::load_or_create(&base_path)?;
+        let creds = FtpCreds::load_or_create(&base_path)?;
+
+        let _lock = LockGuard::acquire(&base_path, &creds, config.lock_ttl())?;
+
+        let mut files_tracking = FilesTracking::load(&base_path)?;
+        if config.shared_tracking() {
+            SharedTracking::load_into(&creds, &mut files_tracking, &mut RemoteTracking::default())?;
+        }
+
+        let root = creds.remote_path(".");
+        println!(
+            "[ftp-deploy] Looking for untracked files on {}{}",
+            creds.server, root
+        );
+
+        // besides the internal files, atomic releases are managed by the deploy itself
+        let mut excluded = vec![creds.state_dir(), creds.remote_path(GIT_FTP_LOG)];
+        if ReleasesTracking::load(&base_path)?.current.is_some() {
+            excluded.push(creds.remote_path("releases"));
+            excluded.push(creds.remote_path("current"));
+        }
+
+        let tracked: HashSet<String> = files_tracking
+            .files
+            .keys()
+            .map(|path| creds.remote_path(path))
+            .collect();
+        let protect = PathMatcher::new(&base_path, config.protect())?;
+
+        let mut ftp_stream = creds.open_stream()?;
+        let entries = ftp_stream
+            .list_recursive(&root)?
+            .into_iter()
+            .filter(|entry| !excluded.iter().any(|dir| entry.is_within(dir)))
+            .collect_vec();
+
+        let (orphans, kept): (Vec<_>, Vec<_>) = entries.into_iter().partition(|entry| {
+            let relative = entry.path[root.len()..].trim_start_matches('/');
+            !tracked.contains(&entry.path)
+                && protect
+                    .matched(&PathBuf::from(relative), entry.is_dir)
+                    .is_none()
+        });
+
+        // directories are only removed once nothing that stays is left in them
+        let (orphan_dirs, orphan_files): (Vec<_>, Vec<_>) =
+            orphans.into_iter().partition(|entry| entry.is_dir);
+        let orphan_dirs = orphan_dirs
+            .into_iter()
+            .filter(|dir| {
+                !kept
+                    .iter()
+                    .any(|entry| entry.path != dir.path && entry.is_within(&dir.path))
+            })
+            .collect_vec();
+
+        if orphan_files.is_empty() && orphan_dirs.is_empty() {
+            println!("[ftp-deploy] No untracked files on the server.");
+            return Ok(());
+        }
+
+        for entry in orphan_files.iter().sorted_by(|a, b| a.path.cmp(&b.path)) {
+            println!("\tuntracked:\t{}", entry.path);
+        }
+        for entry in orphan_dirs.iter().sorted_by(|a, b| a.path.cmp(&b.path)) {
+            println!("\tuntracked:\t{}/", entry.path);
+        }
+
+        if !self.yes
+            && !confirm(&format!(
+                "Delete {} untracked file(s) and {} folder(s) from the server?",
+                orphan_files.len(),
+                orphan_dirs.len()
+            ))
+        {
+            return Err("nothing was deleted, pass --yes to delete without asking".into());
+        }
+
+        let mut failed = 0;
+        for entry in &orphan_files {
+            if let Err(err) = ftp_stream.rm(&entry.path) {
+                println!("[ftp-deploy] Failed to delete '{}': {}", entry.path, err);
+                failed += 1;
+            }
+        }
+
+        // deepest directories first, so every directory is empty when it is removed
+        for entry in orphan_dirs
+            .iter()
+            .sorted_by_key(|entry| std::cmp::Reverse(entry.path.matches('/').count()))
+        {
+            if let Err(err) = ftp_stream.rmdir(&entry.path) {
+                println!("[ftp-deploy] Failed to delete '{}/': {}", entry.path, err);
+                failed += 1;
+            }
+        }
+
+        println!(
+            "[ftp-deploy] Deleted {} untracked entries.",
+            orphan_files.len() + orphan_dirs.len() - failed
+        );
+
+        if failed > 0 {
+            return Err(format!("failed to delete {} entries", failed).into());
+        }
+
+        Ok(())
+    }
+}
@@ -1,164 +1,30 @@
 use std::{
     collections::HashMap,
-    fs::{self, File},
-    io,
+    fs, io,
     path::{Path, PathBuf},
-    sync::{Arc, RwLock},
-    time,
 };
 
+use chrono::{SecondsFormat, Utc};
 use clap::Args;
-use ignore::WalkBuilder;
-use indicatif::{ProgressBar, ProgressStyle};
+use ftp::FtpStream;
 use itertools::Itertools;
-use sha2::{Digest, Sha256};
 
 use crate::{
     commands::SubcommandDelegate,
-    config::{ConfigLoader, FtpConfig, FtpCreds},
+    config::{BackupMode, ConfigLoader, FtpConfig, FtpCreds, IgnoredPolicy},
     ftp::FtpStreamExt,
-    tracking::{FileState, FilesTracking, IGNORE_FILE_NAME, TrackingFileLoder},
+    git,
+    lock::LockGuard,
+    tracking::{
+        ArchiveFiles, BackupSet, BackupsTracking, FileMode, FileState, FileType, FileUpdate,
+        FileUpdateType, FilesTracking, GIT_FTP_LOG, GitFtpLog, GitTree, History, HistoryEntry,
+        PathMatcher, Plan, PlanSource, PlannedUpdate, ReleasesTracking, RemoteTracking,
+        SharedTracking, Snapshots, TRACKING_DIR, TrackingFileLoder, apply_ignored_policy,
+        collect_files, new_deploy_id,
+    },
+    upload::{Backup, FileSource, LocalFiles, UploadReport, Uploader},
 };
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
-enum FileMode {
-    Untouched,
-    Created,
-    Updated,
-    Deleted,
-}
-
-#[derive(Clone)]
-struct FileWalk {
-    files: Arc<RwLock<HashMap<PathBuf, (FileState, FileMode)>>>,
-}
-
-impl FileWalk {
-    fn insert_update(&self, path: PathBuf, state: FileState, mode: FileMode) {
-        self.files
-            .write()
-            .unwrap()
-            .insert(path.clone(), (state, mode));
-    }
-
-    fn update(&self, path: impl AsRef<Path>, state: FileState, force: bool) {
-        if self.files.read().unwrap().contains_key(path.as_ref()) {
-            let mode = if force || self.files.read().unwrap().get(path.as_ref()).unwrap().0 != state
-            {
-                FileMode::Updated
-            } else {
-                FileMode::Untouched
-            };
-
-            self.insert_update(path.as_ref().to_path_buf(), state, mode);
-        } else {
-            self.insert_update(path.as_ref().to_path_buf(), state, FileMode::Created);
-        }
-    }
-}
-
-impl From<FilesTracking> for FileWalk {
-    fn from(value: FilesTracking) -> Self {
-        Self {
-            files: Arc::new(RwLock::new(
-                value
-                    .files
-                    .into_iter()
-                    .map(|(key, value)| (key, (value, FileMode::Deleted)))
-                    .collect(),
-            )),
-        }
-    }
-}
-
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-enum FileUpdateType {
-    CreateOrUpdate,
-    Delete,
-}
-
-impl FileUpdateType {
-    pub fn get_verb(&self) -> &str {
-        match self {
-            FileUpdateType::CreateOrUpdate => "create or update",
-            FileUpdateType::Delete => "delete",
-        }
-    }
-}
-
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-enum FileType {
-    File,
-    Directory,
-}
-
-impl From<&FileState> for FileType {
-    fn from(value: &FileState) -> Self {
-        match value {
-            FileState::File(_) => Self::File,
-            FileState::Directory => Self::Directory,
-        }
-    }
-}
-
-#[derive(Debug, Clone, PartialEq, Eq)]
-struct FileUpdate {
-    update_type: FileUpdateType,
-    file_type: FileType,
-    file: PathBuf,
-}
-
-impl PartialOrd for FileUpdate {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        match (self.update_type, other.update_type) {
-            (FileUpdateType::Delete, FileUpdateType::Delete) => {
-                // sort so that first items with file_type File and then Directory and then by file name
-                match (self.file_type, other.file_type) {
-                    (FileType::File, FileType::Directory) => Some(std::cmp::Ordering::Less),
-                    (FileType::Directory, FileType::File) => Some(std::cmp::Ordering::Greater),
-                    _ => self.file.partial_cmp(&other.file),
-                }
-            }
-            (FileUpdateType::CreateOrUpdate, FileUpdateType::CreateOrUpdate) => {
-                // sort so that first items with file_type Directory and then File and then by file name
-                match (self.file_type, other.file_type) {
-                    (FileType::Directory, FileType::File) => Some(std::cmp::Ordering::Less),
-                    (FileType::File, FileType::Directory) => Some(std::cmp::Ordering::Greater),
-                    _ => self.file.partial_cmp(&other.file),
-                }
-            }
-            (a, b) => a.partial_cmp(&b),
-        }
-    }
-}
-
-impl Ord for FileUpdate {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
-    }
-}
-
-impl FileUpdate {
-    pub fn from_files(files: &HashMap<PathBuf, (FileState, FileMode)>) -> Vec<FileUpdate> {
-        files
-            .iter()
-            .filter_map(|(path, (state, mode))| {
-                let update_mode = match mode {
-                    FileMode::Created | FileMode::Updated => FileUpdateType::CreateOrUpdate,
-                    FileMode::Deleted => FileUpdateType::Delete,
-                    _ => return None,
-                };
-
-                Some(FileUpdate {
-                    file: path.clone(),
-                    file_type: state.into(),
-                    update_type: update_mode,
-                })
-            })
-            .collect()
-    }
-}
-
 #[derive(Args)]
 pub struct DeployCommand {
     /// Directory to initialize the configuration file in
@@ -169,7 +35,7 @@ pub struct DeployCommand {
     #[arg(short, long)]
     jobs: Option<usize>,
 
-    /// Force deploy even if no changes are detected
+    /// Force deploy even if no changes are detected or files were changed on the server
     #[arg(short, long)]
     force: bool,
 
@@ -182,130 +48,448 @@ pub struct DeployCommand {
     no_upload: bool,
 
     /// Debug mode, print additional information
-    #[arg(short, long)]
+    // no short form, `-d` is taken by `--dry`
+    #[arg(long)]
     debug: bool,
+
+    /// Upload a complete release into `releases/<id>` and switch `current` to it once finished
+    #[arg(short, long)]
+    atomic: bool,
+
+    /// Number of previous releases to keep on the server when deploying atomically
+    #[arg(long, default_value_t = 3)]
+    keep_releases: usize,
+
+    /// Upload every file under a temporary name and rename it into place once complete
+    #[arg(long)]
+    temp_upload: bool,
+
+    /// Deploy even if most or all of the tracked files would be deleted
+    #[arg(long)]
+    allow_mass_delete: bool,
+
+    /// Only create and update files, never delete anything on the server
+    #[arg(long, conflicts_with_all = ["delete_only", "atomic"])]
+    no_delete: bool,
+
+    /// Only delete files that were removed locally, do not upload anything
+    #[arg(long, conflicts_with = "atomic")]
+    delete_only: bool,
+
+    /// Only deploy these paths or globs, relative to the deployed directory
+    #[arg(conflicts_with = "atomic")]
+    paths: Vec<String>,
+
+    /// Read the paths to deploy from a file, one per line, or from stdin with `-`
+    #[arg(long, conflicts_with = "atomic")]
+    files_from: Option<PathBuf>,
+
+    /// Only deploy files that changed in git since this commit, branch or tag
+    #[arg(long, conflicts_with_all = ["paths", "files_from", "atomic"])]
+    since: Option<String>,
+
+    /// Deploy the contents of a .tar, .tar.gz or .zip archive instead of the directory
+    #[arg(long, conflicts_with = "since")]
+    from_archive: Option<PathBuf>,
+
+    /// Deploy the files of a git commit, branch or tag instead of the working tree
+    #[arg(long, conflicts_with_all = ["from_archive", "since"])]
+    git_rev: Option<String>,
+
+    /// Write the planned changes to a file for `apply` instead of deploying them
+    #[arg(long, conflicts_with_all = ["atomic", "no_upload"])]
+    plan_out: Option<PathBuf>,
+
+    /// Plan executed by `apply`, the changes are not computed again
+    #[arg(skip)]
+    plan: Option<Box<Plan>>,
 }
 
 impl DeployCommand {
-    fn collect_files(
+    /// A deploy that executes `plan`, reading the files from the source it was made from.
+    pub fn for_plan(path: Option<PathBuf>, plan: Plan) -> Self {
+        let (from_archive, git_rev) = match &plan.source {
+            PlanSource::Directory => (None, None),
+            PlanSource::Archive(archive) => (Some(archive.clone()), None),
+            PlanSource::GitCommit(commit) => (None, Some(commit.clone())),
+        };
+
+        Self {
+            path,
+            jobs: None,
+            force: false,
+            dry: false,
+            no_upload: false,
+            debug: false,
+            atomic: false,
+            keep_releases: 3,
+            temp_upload: plan.temp_upload,
+            // the deletions were reviewed with the plan
+            allow_mass_delete: true,
+            no_delete: false,
+            delete_only: false,
+            paths: Vec::new(),
+            files_from: None,
+            since: None,
+            from_archive,
+            git_rev,
+            plan_out: None,
+            plan: Some(Box::new(plan)),
+        }
+    }
+
+    /// The updates of the plan, as long as every file still has the contents it was planned
+    /// with.
+    fn planned_updates(
+        plan: &Plan,
+        files: &HashMap<PathBuf, (FileState, FileMode)>,
+    ) -> Result<Vec<FileUpdate>, Box<dyn std::error::Error>> {
+        let changed = plan
+            .updates
+            .iter()
+            .map(|planned| &planned.update)
+            .filter(|update| match update.update_type {
+                FileUpdateType::Delete => files.get(&update.file).is_some_and(|(_, mode)| {
+                    matches!(
+                        mode,
+                        FileMode::Created | FileMode::Updated | FileMode::Untouched
+                    )
+                }),
+                FileUpdateType::CreateOrUpdate | FileUpdateType::Move => files
+                    .get(&update.file)
+                    .is_none_or(|(state, _)| *state != update.state),
+            })
+            .map(|update| &update.file)
+            .sorted()
+            .collect_vec();
+
+        if !changed.is_empty() {
+            for file in &changed {
+                println!("[ftp-deploy]   {}", file.display());
+            }
+            return Err(format!(
+                "aborting: {} file(s) changed since the plan was made, create a new plan",
+                changed.len()
+            )
+            .into());
+        }
+
+        Ok(plan
+            .updates
+            .iter()
+            .map(|planned| planned.update.clone())
+            .collect())
+    }
+
+    /// Builds a matcher for the paths selected on the command line, or `None` if the whole
+    /// directory is deployed.
+    fn selection(
         &self,
         base_path: &Path,
-        files_tracking: FilesTracking,
-    ) -> Result<HashMap<PathBuf, (FileState, FileMode)>, Box<dyn std::error::Error>> {
-        let jobs = self.jobs.unwrap_or_else(|| num_cpus::get());
-        let file_walk: FileWalk = files_tracking.into();
-
-        let walker = WalkBuilder::new(&base_path)
-            .add_custom_ignore_filename(IGNORE_FILE_NAME)
-            .hidden(false)
-            .threads(jobs)
-            .build_parallel();
-
-        println!("[ftp-deploy] Collecting files using {} threads", jobs);
-        let start = time::Instant::now();
-
-        walker.run(|| {
-            let file_walk = file_walk.clone();
-            let force = self.force;
-
-            Box::new(move |result| {
-                let Ok(result) = result else {
-                    return ignore::WalkState::Continue;
-                };
+    ) -> Result<Option<PathMatcher>, Box<dyn std::error::Error>> {
+        if let Some(since) = &self.since {
+            let changed = git::changed_since(base_path, since)?;
+            println!(
+                "[ftp-deploy] {} file(s) changed since {}",
+                changed.len(),
+                since
+            );
 
-                let path = result.path();
-                let state = if path.is_file() {
-                    let mut hasher = Sha256::new();
-                    let mut file = fs::File::open(path).unwrap();
-                    io::copy(&mut file, &mut hasher).unwrap();
+            // git reports exact paths
+            let patterns = changed
+                .iter()
+                .map(|file| PathMatcher::literal_pattern(file))
+                .collect_vec();
 
-                    FileState::File(format!("{:x}", hasher.finalize()))
-                } else {
-                    FileState::Directory
-                };
+            return Ok(Some(PathMatcher::new(base_path, &patterns)?));
+        }
+
+        // paths on the command line may be globs, the ones read from a file are taken literally
+        let mut patterns = self
+            .paths
+            .iter()
+            .map(|path| path.trim())
+            .filter(|path| !path.is_empty())
+            .map(PathMatcher::anchored_pattern)
+            .collect_vec();
+
+        if let Some(files_from) = &self.files_from {
+            let contents = if files_from == Path::new("-") {
+                io::read_to_string(io::stdin())?
+            } else {
+                fs::read_to_string(files_from)?
+            };
+            patterns.extend(
+                contents
+                    .lines()
+                    .map(|line| line.trim_end_matches('\r'))
+                    .filter(|line| !line.trim().is_empty())
+                    .map(PathMatcher::literal_pattern),
+            );
+        } else if patterns.is_empty() {
+            return Ok(None);
+        }
 
-                file_walk.update(path, state, force);
+        Ok(Some(PathMatcher::new(base_path, &patterns)?))
+    }
 
-                ignore::WalkState::Continue
-            })
-        });
+    /// Computes the updates of the collected files, honouring the protected paths and the
+    /// deletion options.
+    fn compute_updates(
+        &self,
+        base_path: &Path,
+        config: &FtpConfig,
+        files: &HashMap<PathBuf, (FileState, FileMode)>,
+    ) -> Result<Vec<FileUpdate>, Box<dyn std::error::Error>> {
+        let protect = PathMatcher::new(base_path, config.protect())?;
+        let mut updates = FileUpdate::from_files(files, &protect);
+        // a move deletes the previous path and a skipped upload cannot be moved either
+        if !self.no_delete && !self.delete_only {
+            updates = FileUpdate::detect_moves(updates, files);
+        }
 
-        let files = Arc::try_unwrap(file_walk.files).unwrap().into_inner()?;
-        println!("[ftp-deploy] Collecting files took {:?}.", start.elapsed(),);
+        if !self.allow_mass_delete && !self.no_delete {
+            config.mass_delete().check(files, &updates)?;
+        }
+
+        let moves = updates
+            .iter()
+            .filter(|update| update.update_type == FileUpdateType::Move)
+            .count();
+        if moves > 0 {
+            println!(
+                "[ftp-deploy] {} move(s) detected, renaming on the server instead of uploading again",
+                moves
+            );
+        }
+
+        // skipped updates are not applied, so their tracking entries stay as they are
+        if self.no_delete || self.delete_only {
+            let before = updates.len();
+            updates.retain(|update| {
+                (update.update_type == FileUpdateType::Delete) == self.delete_only
+            });
+            println!(
+                "[ftp-deploy] Skipping {} {} because of --{}",
+                before - updates.len(),
+                if self.no_delete {
+                    "deletion(s)"
+                } else {
+                    "upload(s)"
+                },
+                if self.no_delete {
+                    "no-delete"
+                } else {
+                    "delete-only"
+                },
+            );
+        }
 
-        Ok(files)
+        Ok(updates)
     }
 
-    fn upload_files(
+    /// Uploads every file into a new release directory and renames it to `current`, so the
+    /// site switches from one complete release to the next. Returns the tracking of the release.
+    #[allow(clippy::too_many_arguments)]
+    fn deploy_release(
         &self,
+        id: &str,
         creds: &FtpCreds,
-        updated_files: Vec<FileUpdate>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        println!("[ftp-deploy] Uploading files to {}", creds.server);
+        source: &dyn FileSource,
+        files: &HashMap<PathBuf, (FileState, FileMode)>,
+        protect: &PathMatcher,
+        releases_tracking: &mut ReleasesTracking,
+        remote_tracking: &mut RemoteTracking,
+        report: &mut UploadReport,
+    ) -> Result<FilesTracking, Box<dyn std::error::Error>> {
+        let release = creds.remote_path(format!("releases/{}", id));
+
+        println!("[ftp-deploy] Uploading release {}", id);
+
+        // protected paths are owned by the server, they are taken over from the live release
+        let updates = FileUpdate::all_from_files(files)
+            .into_iter()
+            .filter(|update| {
+                protect
+                    .matched(&update.file, update.file_type == FileType::Directory)
+                    .is_none()
+            })
+            .collect_vec();
+        let expected = updates.len();
+
+        let release_creds = FtpCreds {
+            base_path: PathBuf::from(&release),
+            ..creds.clone()
+        };
+        let mut release_tracking = FilesTracking::default();
+        Uploader {
+            creds: &release_creds,
+            source,
+            force: self.force,
+            backup: None,
+            // the release directory is not live until it is switched to
+            temp_upload: false,
+        }
+        .upload_files(updates, &mut release_tracking, remote_tracking, report)?;
 
         let mut ftp_stream = creds.open_stream()?;
 
-        ftp_stream.cwd("/")?;
-        let mut _current_ftp_path = PathBuf::from("/");
-
-        let style = ProgressStyle::with_template(
-            "{spinner:.green} [{elapsed_precise}] {msg} [{wide_bar:.cyan/blue}] ({eta})",
-        )
-        .unwrap()
-        .progress_chars("#>-");
-        let pb = ProgressBar::new(updated_files.len() as u64).with_style(style);
-
-        for FileUpdate {
-            file,
-            file_type,
-            update_type,
-        } in updated_files.into_iter().sorted()
-        {
-            // TODO: sort file paths and only do necessary mkdir's and cwd's
+        if release_tracking.files.len() != expected {
+            println!(
+                "[ftp-deploy] Release {} is incomplete, not switching to it.",
+                id
+            );
+            ftp_stream.remove_recursive(&release)?;
+            return Err("failed to upload release".into());
+        }
 
-            let Some(file_name) = file.file_name() else {
-                println!("[ftp-deploy] Skipping invalid file {}", file.display());
-                continue;
-            };
+        switch_release(&mut ftp_stream, creds, id, protect, releases_tracking)?;
 
-            let ftp_path = creds.ftp_path(&file);
-            // TODO: get relative path to current path
+        Ok(release_tracking)
+    }
 
-            let file_name: &str = file_name.try_into().unwrap();
+    /// Removes all but the newest `keep_releases` releases. The switch already happened, so
+    /// failures are only reported.
+    fn prune_releases(&self, creds: &FtpCreds) {
+        let releases = creds.remote_path("releases");
 
-            pb.set_message(file_name.to_string());
+        let pruned = creds.open_stream().and_then(|mut ftp_stream| {
+            let old_releases = ftp_stream
+                .nlst(Some(&releases))?
+                .into_iter()
+                .map(|name| name.rsplit('/').next().unwrap_or_default().to_string())
+                .filter(|name| name != "." && name != "..")
+                .sorted()
+                .rev()
+                .skip(self.keep_releases)
+                .collect_vec();
+
+            for old_release in old_releases {
+                println!("[ftp-deploy] Removing old release {}", old_release);
+                ftp_stream.remove_recursive(&format!("{}/{}", releases, old_release))?;
+            }
 
-            ftp_stream.cwd_or_create_recursive(ftp_path.parent())?;
+            Ok(())
+        });
 
-            // TODO: update current path
+        if let Err(err) = pruned {
+            println!("[ftp-deploy] Failed to remove old releases: {}", err);
+        }
+    }
+}
 
-            let res = match update_type {
-                FileUpdateType::Delete => match file_type {
-                    FileType::File => ftp_stream.rm(file_name),
-                    FileType::Directory => ftp_stream.rmdir(file_name),
-                },
-                FileUpdateType::CreateOrUpdate => match file_type {
-                    FileType::Directory => ftp_stream.mkdir(file_name),
-                    FileType::File => {
-                        let mut reader = File::open(&file)?;
-                        ftp_stream.put(file_name, &mut reader)
-                    }
-                },
-            };
+/// Renames the uploaded release `id` to `current`, moving the live release back into
+/// `releases/` and taking over its protected paths. On failure, the live release stays in place.
+pub(super) fn switch_release(
+    ftp_stream: &mut FtpStream,
+    creds: &FtpCreds,
+    id: &str,
+    protect: &PathMatcher,
+    releases_tracking: &mut ReleasesTracking,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let releases = creds.remote_path("releases");
+    let release = format!("{}/{}", releases, id);
+    let current = creds.remote_path("current");
+
+    if ftp_stream.cwd(&current).is_ok() {
+        let previous = match &releases_tracking.current {
+            Some(previous) => format!("{}/{}", releases, previous),
+            None => format!("{}/{}-previous", releases, id),
+        };
 
-            if let Err(err) = res {
+        let carried = match carry_protected(ftp_stream, &current, &release, protect) {
+            Ok(carried) => carried,
+            Err(err) => {
                 println!(
-                    "[ftp-deploy] Failed to {} file '{}': {}",
-                    update_type.get_verb(),
-                    file.display(),
-                    err
+                    "[ftp-deploy] Failed to move the protected paths into release {}, not switching to it.",
+                    id
                 );
+                return Err(err);
             }
+        };
 
-            pb.inc(1);
+        ftp_stream.cwd("/")?;
+        if let Err(err) = ftp_stream.rename(&current, &previous) {
+            return_protected(ftp_stream, &carried, &release, &current);
+            return Err(err.into());
         }
 
-        Ok(())
+        if let Err(err) = ftp_stream.rename(&release, &current) {
+            ftp_stream.rename(&previous, &current)?;
+            return_protected(ftp_stream, &carried, &release, &current);
+            return Err(err.into());
+        }
+    } else {
+        ftp_stream.rename(&release, &current)?;
+    }
+
+    releases_tracking.current = Some(id.to_string());
+    println!("[ftp-deploy] Switched current to release {}", id);
+
+    Ok(())
+}
+
+/// Moves the protected paths of the live release into the new one, returning the moved paths
+/// relative to the release directories. On failure, the paths moved so far are moved back.
+fn carry_protected(
+    ftp_stream: &mut FtpStream,
+    current: &str,
+    release: &str,
+    protect: &PathMatcher,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut carried: Vec<String> = Vec::new();
+
+    // parents come before their contents, which move along with them
+    let entries = ftp_stream
+        .list_recursive(current)?
+        .into_iter()
+        .sorted_by(|a, b| a.path.cmp(&b.path))
+        .collect_vec();
+
+    for entry in entries {
+        let relative = entry.path[current.len()..]
+            .trim_start_matches('/')
+            .to_string();
+        if carried
+            .iter()
+            .any(|moved| relative.starts_with(&format!("{}/", moved)))
+            || protect
+                .matched(Path::new(&relative), entry.is_dir)
+                .is_none()
+        {
+            continue;
+        }
+
+        let destination = format!("{}/{}", release, relative);
+        let moved = ftp_stream
+            .cwd_or_create_recursive(Path::new(&destination).parent())
+            .and_then(|_| ftp_stream.rename(&entry.path, &destination));
+
+        if let Err(err) = moved {
+            return_protected(ftp_stream, &carried, release, current);
+            return Err(err.into());
+        }
+
+        println!("[ftp-deploy] Keeping protected path '{}'", relative);
+        carried.push(relative);
+    }
+
+    Ok(carried)
+}
+
+/// Moves the protected paths taken over by a release that was not switched to back into the
+/// live one.
+fn return_protected(ftp_stream: &mut FtpStream, carried: &[String], release: &str, current: &str) {
+    for relative in carried.iter().rev() {
+        let from = format!("{}/{}", release, relative);
+        let to = format!("{}/{}", current, relative);
+
+        if let Err(err) = ftp_stream.rename(&from, &to) {
+            println!(
+                "[ftp-deploy] Failed to move protected path '{}' back, it is left at '{}': {}",
+                relative, from, err
+            );
+        }
     }
 }
 
@@ -313,17 +497,117 @@ impl SubcommandDelegate for DeployCommand {
     fn run(self) -> Result<(), Box<dyn std::error::Error>> {
         let base_path = self.path.clone().unwrap_or_else(|| PathBuf::from("."));
 
+        let started = Utc::now();
+        let deploy_id = new_deploy_id();
+
         let config = FtpConfig::load_or_create(&base_path)?;
         let creds = FtpCreds::load_or_create(&base_path)?;
 
-        if !config.hooks().is_empty() {
+        let rev_commit = match &self.git_rev {
+            Some(rev) => Some(
+                git::resolve_commit(&base_path, rev)
+                    .ok_or_else(|| format!("unknown git revision '{}'", rev))?,
+            ),
+            None => None,
+        };
+
+        // uncommitted changes are not deployed with --git-rev
+        if config.require_clean() && rev_commit.is_none() {
+            match git::is_dirty(&base_path, &[TRACKING_DIR]) {
+                Some(false) => {}
+                Some(true) => {
+                    return Err(
+                        "aborting: the git working tree has uncommitted changes, commit them first"
+                            .into(),
+                    );
+                }
+                None => {
+                    return Err(
+                        "aborting: require_clean is set, but the project is not a git repository"
+                            .into(),
+                    );
+                }
+            }
+        }
+
+        // the plan was computed from the output of the hooks already
+        if self.plan.is_none() && !config.hooks().is_empty() {
             println!("[ftp-deploy] Running {} hook(s)", config.hooks().len());
             config.run_hooks();
         }
 
-        let files_tracking = FilesTracking::load_or_create(&base_path)?;
+        // held until the end of the deploy, also guards the tracking files
+        let _lock = if self.dry || self.plan_out.is_some() {
+            None
+        } else {
+            Some(LockGuard::acquire(&base_path, &creds, config.lock_ttl())?)
+        };
+
+        let mut files_tracking = FilesTracking::load_or_create(&base_path)?;
+        let mut remote_tracking = RemoteTracking::load_or_create(&base_path)?;
+        let mut shared = if config.shared_tracking() {
+            Some(SharedTracking::load_into(
+                &creds,
+                &mut files_tracking,
+                &mut remote_tracking,
+            )?)
+        } else {
+            None
+        };
+        let mut releases_tracking = ReleasesTracking::load_or_create(&base_path)?;
+
+        let target = format!("{}{}", creds.server, creds.remote_path("."));
+        if let Some(plan) = &self.plan {
+            if plan.target != target {
+                return Err(format!(
+                    "aborting: the plan was made for {}, not for {}",
+                    plan.target, target
+                )
+                .into());
+            }
+            if plan.tracking != Plan::tracking_digest(&files_tracking, &remote_tracking) {
+                return Err(
+                    "aborting: the tracking changed since the plan was made, create a new plan"
+                        .into(),
+                );
+            }
+        }
 
-        let files = self.collect_files(&base_path, files_tracking)?;
+        // files outside of the selection are left untouched, also in the tracking files
+        let only = self.selection(&base_path)?;
+        let mut archive = self.from_archive.as_ref().map(ArchiveFiles::new);
+        let mut tree = rev_commit
+            .as_ref()
+            .map(|commit| GitTree::new(&base_path, commit));
+        let mut files = if let Some(archive) = &mut archive {
+            archive.collect(
+                &base_path,
+                files_tracking.clone(),
+                self.force,
+                only.as_ref(),
+            )?
+        } else if let Some(tree) = &mut tree {
+            tree.collect(files_tracking.clone(), self.force, only.as_ref())?
+        } else {
+            collect_files(
+                &base_path,
+                files_tracking.clone(),
+                self.jobs.unwrap_or_else(num_cpus::get),
+                self.force,
+                only.as_ref(),
+            )?
+        };
+        let source: &dyn FileSource = match (&archive, &tree) {
+            (Some(archive), _) => archive,
+            (_, Some(tree)) => tree,
+            _ => &LocalFiles,
+        };
+        // a dry run, applying or writing a plan must not prompt, the files are listed as
+        // ignored instead
+        let interactive = !self.dry && self.plan.is_none() && self.plan_out.is_none();
+        if interactive || config.ignored() != IgnoredPolicy::Ask {
+            apply_ignored_policy(&mut files, config.ignored());
+        }
 
         println!(
             "[ftp-deploy] {} file(s) created, {} file(s) updated, {} file(s) were deleted",
@@ -341,6 +625,28 @@ impl SubcommandDelegate for DeployCommand {
                 .count(),
         );
 
+        // only the files the plan lists are released, anything else was not reviewed
+        let ignored = files
+            .iter()
+            .filter(|(_, (_, mode))| *mode == FileMode::Ignored)
+            .map(|(path, _)| path)
+            .filter(|path| {
+                self.plan
+                    .as_ref()
+                    .is_none_or(|plan| plan.ignored.contains(path))
+            })
+            .sorted()
+            .collect_vec();
+        if !ignored.is_empty() {
+            println!(
+                "[ftp-deploy] {} file(s) are ignored now, keeping them on the server and no longer tracking them:",
+                ignored.len()
+            );
+            for path in &ignored {
+                println!("[ftp-deploy]   {}", path.display());
+            }
+        }
+
         if self.debug {
             println!(
                 "{}",
@@ -351,27 +657,266 @@ impl SubcommandDelegate for DeployCommand {
             );
         }
 
-        let updates = FileUpdate::from_files(&files);
-        let files_tracking = FilesTracking {
-            files: files
-                .into_iter()
-                .filter_map(|(path, (state, mode))| match mode {
-                    FileMode::Deleted => None,
-                    _ => Some((path, state)),
-                })
-                .collect(),
+        let updates = match &self.plan {
+            Some(plan) => Self::planned_updates(plan, &files)?,
+            None => self.compute_updates(&base_path, &config, &files)?,
         };
 
+        if let Some(plan_out) = &self.plan_out {
+            let plan = Plan {
+                created: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+                target,
+                tracking: Plan::tracking_digest(&files_tracking, &remote_tracking),
+                source: match (&self.from_archive, &rev_commit) {
+                    (Some(archive), _) => PlanSource::Archive(fs::canonicalize(archive)?),
+                    (_, Some(commit)) => PlanSource::GitCommit(commit.clone()),
+                    _ => PlanSource::Directory,
+                },
+                partial: only.is_some(),
+                temp_upload: self.temp_upload,
+                ignored: ignored.iter().map(|path| path.to_path_buf()).collect(),
+                updates: updates
+                    .iter()
+                    .map(|update| PlannedUpdate {
+                        size: match update.update_type {
+                            FileUpdateType::CreateOrUpdate => {
+                                source.size(&update.file, &update.state)
+                            }
+                            _ => None,
+                        },
+                        update: update.clone(),
+                    })
+                    .collect(),
+            };
+            plan.write(plan_out)?;
+
+            println!(
+                "[ftp-deploy] Wrote a plan of {} change(s) to {}, run `ftp-deploy apply {}` to deploy it",
+                plan.updates.len(),
+                plan_out.display(),
+                plan_out.display()
+            );
+            return Ok(());
+        }
+
         if !self.dry {
-            if !self.no_upload && !updates.is_empty() {
-                self.upload_files(&creds, updates)?;
+            let deployed = !self.no_upload && (!updates.is_empty() || self.atomic && self.force);
+
+            let history_entry = |report| {
+                let command = if self.plan.is_some() {
+                    "apply"
+                } else {
+                    "deploy"
+                };
+                let mut entry =
+                    HistoryEntry::new(&deploy_id, command, started, &base_path, &creds, report);
+                // the checked out commit has nothing to do with what was deployed
+                if rev_commit.is_some() || archive.is_some() {
+                    entry.commit = rev_commit.clone();
+                    entry.dirty = false;
+                }
+                entry
+            };
+
+            let mut report = UploadReport::default();
+            let uploaded = if self.atomic && deployed {
+                let protect = PathMatcher::new(&base_path, config.protect())?;
+                self.deploy_release(
+                    &deploy_id,
+                    &creds,
+                    source,
+                    &files,
+                    &protect,
+                    &mut releases_tracking,
+                    &mut remote_tracking,
+                    &mut report,
+                )
+                .map(|release_tracking| files_tracking = release_tracking)
+            } else if deployed {
+                Uploader {
+                    creds: &creds,
+                    source,
+                    force: self.force,
+                    backup: Backup::new(config.backup().mode, &deploy_id, &base_path, &creds),
+                    temp_upload: self.temp_upload,
+                }
+                .upload_files(
+                    updates,
+                    &mut files_tracking,
+                    &mut remote_tracking,
+                    &mut report,
+                )
             } else {
-                println!("[ftp-deploy] No files to upload.")
+                println!("[ftp-deploy] No files to upload.");
+
+                for update in &updates {
+                    update.apply(&mut files_tracking);
+                }
+                Ok(())
+            };
+
+            if let Err(err) = uploaded {
+                let entry = history_entry(report).aborted(err.as_ref());
+                if let Err(history_err) =
+                    History::new(&base_path).record(&entry, &creds, config.upload_history())
+                {
+                    println!(
+                        "[ftp-deploy] Failed to record the failed deploy: {}",
+                        history_err
+                    );
+                }
+                return Err(err);
+            }
+
+            for path in &ignored {
+                files_tracking.files.remove(*path);
+                remote_tracking.files.remove(*path);
             }
 
             files_tracking.write(&base_path)?;
+            remote_tracking.write(&base_path)?;
+            releases_tracking.write(&base_path)?;
+            if let Some(tree) = &tree {
+                tree.write_blobs()?;
+            }
+
+            if let Some(shared) = &mut shared {
+                shared.store(&creds, &files_tracking, &remote_tracking)?;
+            }
+
+            if self.atomic && deployed {
+                self.prune_releases(&creds);
+            }
+
+            if deployed && config.snapshots() > 0 {
+                let snapshots = Snapshots::new(&base_path);
+                snapshots.save(&deploy_id, &files_tracking, source)?;
+                snapshots.prune(config.snapshots())?;
+            }
+
+            if deployed && config.backup().mode != BackupMode::None {
+                let mut backups_tracking = BackupsTracking::load(&base_path)?;
+                backups_tracking.record(
+                    BackupSet {
+                        id: deploy_id.clone(),
+                        mode: config.backup().mode,
+                        files: report.backed_up.clone(),
+                    },
+                    config.backup().keep,
+                    &base_path,
+                    &creds,
+                )?;
+                backups_tracking.write(&base_path)?;
+            }
+
+            if config.git_ftp_log() {
+                // git-ftp deploys the whole tree of the commit, anything less must not be
+                // recorded as that commit
+                let commit = match &rev_commit {
+                    Some(commit) => Some(commit.clone()),
+                    None => git::head_commit(&base_path)
+                        .filter(|_| git::is_dirty(&base_path, &[TRACKING_DIR]) == Some(false)),
+                }
+                .filter(|_| {
+                    only.is_none()
+                        && !self.plan.as_ref().is_some_and(|plan| plan.partial)
+                        && archive.is_none()
+                        && report.failed.is_empty()
+                });
+
+                match commit {
+                    Some(commit) => GitFtpLog::new(&creds).write(&creds, &commit)?,
+                    None => println!(
+                        "[ftp-deploy] Not updating {}, the deploy does not match a commit.",
+                        GIT_FTP_LOG
+                    ),
+                }
+            }
+
+            if deployed {
+                History::new(&base_path).record(
+                    &history_entry(report),
+                    &creds,
+                    config.upload_history(),
+                )?;
+            }
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(hash: &str) -> FileState {
+        FileState::File(hash.repeat(64))
+    }
+
+    fn plan(updates: &[(FileUpdateType, &str, FileState)]) -> Plan {
+        Plan {
+            created: String::new(),
+            target: String::new(),
+            tracking: String::new(),
+            source: PlanSource::Directory,
+            partial: false,
+            temp_upload: false,
+            ignored: Vec::new(),
+            updates: updates
+                .iter()
+                .map(|(update_type, path, state)| PlannedUpdate {
+                    update: FileUpdate {
+                        update_type: *update_type,
+                        file_type: state.into(),
+                        file: PathBuf::from(path),
+                        state: state.clone(),
+                        from: None,
+                    },
+                    size: None,
+                })
+                .collect(),
+        }
+    }
+
+    fn files(files: &[(&str, FileState, FileMode)]) -> HashMap<PathBuf, (FileState, FileMode)> {
+        files
+            .iter()
+            .map(|(path, state, mode)| (PathBuf::from(path), (state.clone(), *mode)))
+            .collect()
+    }
+
+    #[test]
+    fn applies_unchanged_plans() {
+        let plan = plan(&[
+            (FileUpdateType::CreateOrUpdate, "./a.css", file("a")),
+            (FileUpdateType::Delete, "./old.css", file("b")),
+        ]);
+        let files = files(&[
+            ("./a.css", file("a"), FileMode::Updated),
+            ("./old.css", file("b"), FileMode::Deleted),
+        ]);
+
+        let updates = DeployCommand::planned_updates(&plan, &files).unwrap();
+        assert_eq!(updates.len(), 2);
+    }
+
+    #[test]
+    fn refuses_plans_of_changed_files() {
+        let plan = plan(&[(FileUpdateType::CreateOrUpdate, "./a.css", file("a"))]);
+
+        let changed = files(&[("./a.css", file("c"), FileMode::Updated)]);
+        assert!(DeployCommand::planned_updates(&plan, &changed).is_err());
+
+        let gone = files(&[]);
+        assert!(DeployCommand::planned_updates(&plan, &gone).is_err());
+    }
+
+    #[test]
+    fn refuses_deleting_files_that_are_back() {
+        let plan = plan(&[(FileUpdateType::Delete, "./old.css", file("b"))]);
+        let files = files(&[("./old.css", file("b"), FileMode::Created)]);
+
+        assert!(DeployCommand::planned_updates(&plan, &files).is_err());
+    }
+}
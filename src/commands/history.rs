@@ -0,0 +1,150 @@
+use std::path::PathBuf;
+
+use chrono::NaiveDate;
+use clap::{Args, Subcommand};
+use indicatif::HumanBytes;
+
+use crate::{
+    commands::SubcommandDelegate,
+    tracking::{History, HistoryEntry, RunStatus},
+};
+
+#[derive(Subcommand)]
+enum HistoryAction {
+    /// Show everything recorded about a single deploy
+    Show {
+        /// Id of the deploy to show
+        id: String,
+    },
+}
+
+#[derive(Args)]
+pub struct HistoryCommand {
+    /// Directory to show the deploy history of
+    #[arg(short, long, global = true)]
+    path: Option<PathBuf>,
+
+    /// Only show deploys by this user
+    #[arg(short, long)]
+    user: Option<String>,
+
+    /// Only show deploys from this host
+    #[arg(long)]
+    host: Option<String>,
+
+    /// Only show deploys on or after this date (YYYY-MM-DD)
+    #[arg(short, long)]
+    since: Option<NaiveDate>,
+
+    /// Only show deploys that touched this file, as tracked (e.g. ./site/index.html)
+    #[arg(short, long)]
+    file: Option<PathBuf>,
+
+    /// Only show the last n deploys
+    #[arg(short = 'n', long)]
+    limit: Option<usize>,
+
+    #[command(subcommand)]
+    action: Option<HistoryAction>,
+}
+
+impl HistoryCommand {
+    fn matches(&self, entry: &HistoryEntry) -> bool {
+        self.user.as_ref().is_none_or(|user| &entry.user == user)
+            && self.host.as_ref().is_none_or(|host| &entry.host == host)
+            && self.since.is_none_or(|since| {
+                // RFC 3339 UTC timestamps sort lexicographically
+                entry.timestamp.as_str() >= since.format("%Y-%m-%d").to_string().as_str()
+            })
+            && self.file.as_ref().is_none_or(|file| entry.touches(file))
+    }
+}
+
+fn print_files(label: &str, files: &[PathBuf]) {
+    for file in files {
+        println!("\t{}:\t{}", label, file.display());
+    }
+}
+
+impl SubcommandDelegate for HistoryCommand {
+    fn run(self) -> Result<(), Box<dyn std::error::Error>> {
+        let base_path = self.path.clone().unwrap_or_else(|| PathBuf::from("."));
+        let entries = History::new(&base_path).entries()?;
+
+        if let Some(HistoryAction::Show { id }) = &self.action {
+            let entry = entries
+                .iter()
+                .find(|entry| &entry.id == id)
+                .ok_or_else(|| format!("unknown deploy id '{}'", id))?;
+
+            println!("{} {}", entry.command, entry.id);
+            println!("Date:     {}", entry.timestamp);
+            println!("By:       {}@{}", entry.user, entry.host);
+            println!("Target:   {}", entry.target);
+            match &entry.error {
+                Some(error) => println!("Status:   {} ({})", entry.status.as_str(), error),
+                None => println!("Status:   {}", entry.status.as_str()),
+            }
+            if let Some(commit) = &entry.commit {
+                println!(
+                    "Commit:   {}{}",
+                    commit,
+                    if entry.dirty { " (dirty)" } else { "" }
+                );
+            }
+            println!("Uploaded: {}", HumanBytes(entry.bytes));
+            println!("Duration: {:.1}s", entry.duration_ms as f64 / 1000.0);
+            println!();
+            print_files("created", &entry.created);
+            print_files("updated", &entry.updated);
+            print_files("deleted", &entry.deleted);
+            print_files("moved", &entry.moved);
+            print_files("failed", &entry.failed);
+
+            return Ok(());
+        }
+
+        let matching: Vec<_> = entries.iter().filter(|entry| self.matches(entry)).collect();
+        let skip = self
+            .limit
+            .map_or(0, |limit| matching.len().saturating_sub(limit));
+
+        if matching.is_empty() {
+            println!("[ftp-deploy] No deploys recorded.");
+        }
+
+        for entry in matching.into_iter().skip(skip) {
+            println!(
+                "{}\t{}\t{}{}\t{}@{}\t{} created, {} updated, {} deleted, {} moved, {} failed\t{}{}",
+                entry.id,
+                entry.timestamp,
+                entry.command,
+                match entry.status {
+                    RunStatus::Complete => String::new(),
+                    status => format!(" ({})", status.as_str()),
+                },
+                entry.user,
+                entry.host,
+                entry.created.len(),
+                entry.updated.len(),
+                entry.deleted.len(),
+                entry.moved.len(),
+                entry.failed.len(),
+                HumanBytes(entry.bytes),
+                entry
+                    .commit
+                    .as_ref()
+                    .map(|commit| {
+                        format!(
+                            "\t{}{}",
+                            &commit[..commit.len().min(10)],
+                            if entry.dirty { "-dirty" } else { "" }
+                        )
+                    })
+                    .unwrap_or_default(),
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand};
+
+use crate::{
+    commands::SubcommandDelegate,
+    config::{ConfigLoader, FtpCreds},
+    lock::{read_local, read_remote, remove_local, remove_remote},
+    prompt::confirm,
+};
+
+#[derive(Subcommand)]
+enum LockAction {
+    /// Show whether a deploy currently holds the lock
+    Status,
+
+    /// Remove the lock of a deploy that is gone
+    Break {
+        /// Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+}
+
+#[derive(Args)]
+pub struct LockCommand {
+    /// Directory to manage the deploy lock of
+    #[arg(short, long, global = true)]
+    path: Option<PathBuf>,
+
+    #[command(subcommand)]
+    action: LockAction,
+}
+
+impl SubcommandDelegate for LockCommand {
+    fn run(self) -> Result<(), Box<dyn std::error::Error>> {
+        let base_path = self.path.unwrap_or_else(|| PathBuf::from("."));
+        let creds = FtpCreds::load_or_create(&base_path)?;
+
+        let remote = read_remote(&creds)?;
+        let local = read_local(&base_path)?;
+
+        match self.action {
+            LockAction::Status => {
+                match &remote {
+                    Some(lock) => println!("[ftp-deploy] Server locked by {}", lock),
+                    None => println!("[ftp-deploy] Server is not locked."),
+                }
+                match &local {
+                    Some(lock) => println!("[ftp-deploy] Local lock held by {}", lock),
+                    None => println!("[ftp-deploy] No local lock."),
+                }
+            }
+            LockAction::Break { yes } => {
+                if remote.is_none() && local.is_none() {
+                    println!("[ftp-deploy] Nothing is locked.");
+                    return Ok(());
+                }
+
+                if !yes && !confirm("Break the deploy lock? A running deploy may corrupt the site.")
+                {
+                    return Err("lock was not broken".into());
+                }
+
+                if let Some(lock) = remote {
+                    remove_remote(&creds)?;
+                    println!("[ftp-deploy] Broke the server lock of {}", lock);
+                }
+                if let Some(lock) = local {
+                    remove_local(&base_path)?;
+                    println!("[ftp-deploy] Broke the local lock of {}", lock);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -7,6 +7,36 @@ pub use init::*;
 mod files;
 pub use files::*;
 
+mod status;
+pub use status::*;
+
+mod verify;
+pub use verify::*;
+
+mod adopt;
+pub use adopt::*;
+
+mod pull;
+pub use pull::*;
+
+mod rollback;
+pub use rollback::*;
+
+mod backups;
+pub use backups::*;
+
+mod history;
+pub use history::*;
+
+mod lock;
+pub use lock::*;
+
+mod clean;
+pub use clean::*;
+
+mod apply;
+pub use apply::*;
+
 pub trait SubcommandDelegate {
     fn run(self) -> Result<(), Box<dyn std::error::Error>>;
 }
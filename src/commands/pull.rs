@@ -0,0 +1,292 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    thread,
+};
+
+use clap::Args;
+use ignore::gitignore::Gitignore;
+use indicatif::{ProgressBar, ProgressStyle};
+use itertools::Itertools;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    commands::SubcommandDelegate,
+    config::{ConfigLoader, FtpConfig, FtpCreds},
+    ftp::FtpStreamExt,
+    prompt::confirm,
+    tracking::{FilesTracking, GIT_FTP_LOG, RemoteTracking, TrackingFileLoder, collect_files},
+};
+
+#[derive(Args)]
+pub struct PullCommand {
+    /// Directory containing the configuration and credentials
+    #[arg(short, long)]
+    path: Option<PathBuf>,
+
+    /// Directory to download the remote files into, defaults to the project directory
+    #[arg(short, long)]
+    into: Option<PathBuf>,
+
+    /// File with .gitignore style patterns of remote paths not to download
+    #[arg(long)]
+    ignore: Option<PathBuf>,
+
+    /// Number of parallel connections to download with
+    #[arg(short, long, default_value_t = 4)]
+    jobs: usize,
+
+    /// Write tracking information for the downloaded files, so the next deploy is a no-op
+    #[arg(short, long)]
+    track: bool,
+
+    /// Overwrite local files that differ from the server without asking
+    #[arg(short, long)]
+    force: bool,
+}
+
+/// Name a file is downloaded to while a local file is in its place.
+fn pull_path(destination: &Path) -> PathBuf {
+    let name = destination
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    destination.with_file_name(format!(".{}.ftp-deploy-pull", name))
+}
+
+fn file_sha256(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+impl SubcommandDelegate for PullCommand {
+    fn run(self) -> Result<(), Box<dyn std::error::Error>> {
+        let base_path = self.path.unwrap_or_else(|| PathBuf::from("."));
+        let into = self.into.unwrap_or_else(|| base_path.clone());
+
+        let creds = FtpCreds::load_or_create(&base_path)?;
+
+        // the tracking is written for the download directory, not for everyone deploying the site
+        if self.track && FtpConfig::load_or_create(&base_path)?.shared_tracking() {
+            return Err(
+                "--track cannot be used with shared_tracking, run `ftp-deploy adopt` after pulling instead"
+                    .into(),
+            );
+        }
+
+        let ignore = match &self.ignore {
+            Some(file) => match Gitignore::new(file) {
+                (_, Some(err)) => return Err(err.into()),
+                (ignore, None) => Some(ignore),
+            },
+            None => None,
+        };
+
+        let root = creds.remote_path(".");
+        println!(
+            "[ftp-deploy] Pulling {}{} into \"{}\"",
+            creds.server,
+            root,
+            into.display()
+        );
+
+        let internal = creds.state_dir();
+        let git_ftp_log = creds.remote_path(GIT_FTP_LOG);
+
+        let entries = creds
+            .open_stream()?
+            .list_recursive(&root)?
+            .into_iter()
+            .filter(|entry| !entry.is_within(&internal) && entry.path != git_ftp_log)
+            .filter_map(|entry| {
+                let relative =
+                    PathBuf::from(entry.path.strip_prefix(&root)?.trim_start_matches('/'));
+
+                if let Some(ignore) = &ignore
+                    && ignore
+                        .matched_path_or_any_parents(&relative, entry.is_dir)
+                        .is_ignore()
+                {
+                    return None;
+                }
+
+                Some((into.join(relative), entry))
+            })
+            .collect_vec();
+
+        fs::create_dir_all(&into)?;
+        for (destination, entry) in &entries {
+            if entry.is_dir {
+                fs::create_dir_all(destination)?;
+            } else if let Some(parent) = destination.parent() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+
+        let files = entries
+            .iter()
+            .filter(|(_, entry)| !entry.is_dir)
+            .collect_vec();
+
+        let style = ProgressStyle::with_template(
+            "{spinner:.green} [{elapsed_precise}] {msg} [{wide_bar:.cyan/blue}] ({eta})",
+        )
+        .unwrap()
+        .progress_chars("#>-");
+        let pb = ProgressBar::new(files.len() as u64).with_style(style);
+
+        let jobs = self.jobs.max(1);
+        let results = thread::scope(|scope| {
+            let handles = (0..jobs)
+                .map(|job| {
+                    let chunk = files.iter().skip(job).step_by(jobs).collect_vec();
+                    let (creds, pb) = (&creds, &pb);
+
+                    scope.spawn(move || {
+                        let mut ftp_stream = creds.open_stream().map_err(|err| err.to_string())?;
+                        let mut pulled = Vec::new();
+                        let mut differing = Vec::new();
+
+                        for (destination, entry) in chunk {
+                            pb.set_message(entry.path.clone());
+
+                            // local files are only replaced once it is clear they differ
+                            let existing = destination.is_file();
+                            let target = if existing {
+                                pull_path(destination)
+                            } else {
+                                destination.clone()
+                            };
+
+                            if let Err(err) = ftp_stream.download(&entry.path, &target) {
+                                pb.println(format!(
+                                    "[ftp-deploy] Failed to download '{}': {}",
+                                    entry.path, err
+                                ));
+                                if existing {
+                                    let _ = fs::remove_file(&target);
+                                }
+                                pb.inc(1);
+                                continue;
+                            }
+
+                            let state = ftp_stream.remote_state(&entry.path).ok().flatten();
+                            if !existing {
+                                pulled.push((destination.clone(), state));
+                            } else if matches!(
+                                (file_sha256(&target), file_sha256(destination)),
+                                (Ok(downloaded), Ok(local)) if downloaded == local
+                            ) {
+                                let _ = fs::remove_file(&target);
+                                pulled.push((destination.clone(), state));
+                            } else {
+                                differing.push((destination.clone(), target, state));
+                            }
+
+                            pb.inc(1);
+                        }
+
+                        Ok::<_, String>((pulled, differing))
+                    })
+                })
+                .collect_vec();
+
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect_vec()
+        });
+        pb.finish_and_clear();
+
+        let mut pulled = HashMap::new();
+        let mut differing = Vec::new();
+        for result in results {
+            match result {
+                Ok((files, changed)) => {
+                    pulled.extend(files);
+                    differing.extend(changed);
+                }
+                Err(err) => println!("[ftp-deploy] Failed to open connection: {}", err),
+            }
+        }
+
+        if !differing.is_empty() {
+            differing.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+            for (destination, _, _) in &differing {
+                println!("\tdiffers:\t{}", destination.display());
+            }
+
+            let overwrite = self.force
+                || confirm(&format!(
+                    "Overwrite {} local file(s) with the version on the server?",
+                    differing.len()
+                ));
+
+            for (destination, downloaded, state) in differing {
+                if overwrite {
+                    fs::rename(&downloaded, &destination)?;
+                    pulled.insert(destination, state);
+                } else {
+                    fs::remove_file(&downloaded)?;
+                }
+            }
+
+            if !overwrite {
+                println!(
+                    "[ftp-deploy] Kept the local files that differ, pass --force to overwrite them."
+                );
+            }
+        }
+
+        println!(
+            "[ftp-deploy] Pulled {} of {} file(s).",
+            pulled.len(),
+            files.len()
+        );
+
+        if !self.track {
+            return Ok(());
+        }
+
+        let mut tracked: HashSet<PathBuf> = entries
+            .iter()
+            .filter(|(_, entry)| entry.is_dir)
+            .map(|(destination, _)| destination.clone())
+            .chain(pulled.keys().cloned())
+            .collect();
+        tracked.insert(into.clone());
+
+        let mut files_tracking = FilesTracking::load_or_create(&into)?;
+        let mut remote_tracking = RemoteTracking::load_or_create(&into)?;
+
+        let local_files = collect_files(
+            &into,
+            FilesTracking::default(),
+            num_cpus::get(),
+            false,
+            None,
+        )?;
+        for (path, (state, _)) in local_files {
+            if tracked.contains(&path) {
+                files_tracking.files.insert(path, state);
+            }
+        }
+
+        for (path, state) in pulled {
+            if let Some(state) = state {
+                remote_tracking.files.insert(path, state);
+            }
+        }
+
+        files_tracking.write(&into)?;
+        remote_tracking.write(&into)?;
+
+        println!("[ftp-deploy] Updated tracking information.");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,286 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use chrono::Utc;
+use clap::Args;
+use itertools::Itertools;
+
+use crate::{
+    commands::SubcommandDelegate,
+    config::{BackupMode, ConfigLoader, FtpConfig, FtpCreds},
+    lock::LockGuard,
+    tracking::{
+        BackupSet, BackupsTracking, FileMode, FileUpdate, FilesTracking, History, HistoryEntry,
+        PathMatcher, ReleasesTracking, RemoteTracking, SharedTracking, Snapshots,
+        TrackingFileLoder, new_deploy_id,
+    },
+    upload::{Backup, UploadReport, Uploader},
+};
+
+#[derive(Args)]
+pub struct RollbackCommand {
+    /// Directory to roll back the deployment of
+    #[arg(short, long)]
+    path: Option<PathBuf>,
+
+    /// Id of the deploy to roll back to, defaults to the one before the last deploy
+    #[arg(short, long)]
+    to: Option<String>,
+
+    /// Dry run, only show what would be changed
+    #[arg(short, long)]
+    dry: bool,
+
+    /// Overwrite files that were changed on the server
+    #[arg(short, long)]
+    force: bool,
+
+    /// Upload every file under a temporary name and rename it into place once complete
+    #[arg(long)]
+    temp_upload: bool,
+
+    /// Roll back even if most or all of the tracked files would be deleted
+    #[arg(long)]
+    allow_mass_delete: bool,
+}
+
+impl SubcommandDelegate for RollbackCommand {
+    fn run(self) -> Result<(), Box<dyn std::error::Error>> {
+        let started = Utc::now();
+        let base_path = self.path.unwrap_or_else(|| PathBuf::from("."));
+
+        let config = FtpConfig::load_or_create(&base_path)?;
+        let creds = FtpCreds::load_or_create(&base_path)?;
+
+        let _lock = if self.dry {
+            None
+        } else {
+            Some(LockGuard::acquire(&base_path, &creds, config.lock_ttl())?)
+        };
+
+        let snapshots = Snapshots::new(&base_path);
+        let ids = snapshots.ids()?;
+        let mut releases_tracking = ReleasesTracking::load_or_create(&base_path)?;
+
+        let target_id = match (self.to, &releases_tracking.current) {
+            (Some(id), _) if ids.contains(&id) => id,
+            (Some(id), _) => return Err(format!("unknown deploy id '{}'", id).into()),
+            // after an atomic rollback, the live release is no longer the last deploy
+            (None, Some(current)) => ids
+                .iter()
+                .rev()
+                .find(|id| *id < current)
+                .cloned()
+                .ok_or("no previous deploy to roll back to")?,
+            (None, None) => ids
+                .iter()
+                .rev()
+                .nth(1)
+                .cloned()
+                .ok_or("no previous deploy to roll back to")?,
+        };
+
+        let target = snapshots.load(&target_id)?;
+        let mut files_tracking = FilesTracking::load_or_create(&base_path)?;
+        let mut remote_tracking = RemoteTracking::load_or_create(&base_path)?;
+        let mut shared = if config.shared_tracking() {
+            Some(SharedTracking::load_into(
+                &creds,
+                &mut files_tracking,
+                &mut remote_tracking,
+            )?)
+        } else {
+            None
+        };
+
+        if releases_tracking.current.is_some() {
+            let protect = PathMatcher::new(&base_path, config.protect())?;
+            if releases_tracking.current.as_ref() == Some(&target_id) {
+                return Err(format!("deploy {} is already live", target_id).into());
+            }
+
+            println!(
+                "[ftp-deploy] Rolling back to release {}, switching current back to it",
+                target_id
+            );
+            if self.dry {
+                return Ok(());
+            }
+
+            let release = creds.remote_path(format!("releases/{}", target_id));
+            let mut ftp_stream = creds.open_stream()?;
+            if ftp_stream.cwd(&release).is_err() {
+                return Err(
+                    format!("release {} is no longer kept on the server", target_id).into(),
+                );
+            }
+            super::deploy::switch_release(
+                &mut ftp_stream,
+                &creds,
+                &target_id,
+                &protect,
+                &mut releases_tracking,
+            )?;
+            let _ = ftp_stream.quit();
+
+            // the recorded remote states of changed files are those of the release switched away from
+            for (path, state) in &files_tracking.files {
+                if target.files.get(path) != Some(state) {
+                    remote_tracking.files.remove(path);
+                }
+            }
+            files_tracking.files = target.files;
+            files_tracking.write(&base_path)?;
+            remote_tracking.write(&base_path)?;
+            releases_tracking.write(&base_path)?;
+
+            if let Some(shared) = &mut shared {
+                shared.store(&creds, &files_tracking, &remote_tracking)?;
+            }
+
+            History::new(&base_path).record(
+                &HistoryEntry::new(
+                    &new_deploy_id(),
+                    "rollback",
+                    started,
+                    &base_path,
+                    &creds,
+                    UploadReport::default(),
+                ),
+                &creds,
+                config.upload_history(),
+            )?;
+
+            println!("[ftp-deploy] Rolled back to deploy {}.", target_id);
+            return Ok(());
+        }
+
+        let mut files = HashMap::new();
+        for (path, state) in &target.files {
+            let mode = match files_tracking.files.get(path) {
+                None => FileMode::Created,
+                Some(current) if current != state => FileMode::Updated,
+                Some(_) => FileMode::Untouched,
+            };
+
+            files.insert(path.clone(), (state.clone(), mode));
+        }
+        for (path, state) in &files_tracking.files {
+            if !target.files.contains_key(path) {
+                files.insert(path.clone(), (state.clone(), FileMode::Deleted));
+            }
+        }
+
+        let protect = PathMatcher::new(&base_path, config.protect())?;
+        let updates = FileUpdate::detect_moves(FileUpdate::from_files(&files, &protect), &files);
+
+        println!(
+            "[ftp-deploy] Rolling back to deploy {}: {} file(s) created, {} file(s) updated, {} file(s) deleted",
+            target_id,
+            files
+                .values()
+                .filter(|(_, mode)| *mode == FileMode::Created)
+                .count(),
+            files
+                .values()
+                .filter(|(_, mode)| *mode == FileMode::Updated)
+                .count(),
+            files
+                .values()
+                .filter(|(_, mode)| *mode == FileMode::Deleted)
+                .count(),
+        );
+
+        if !self.allow_mass_delete {
+            config.mass_delete().check(&files, &updates)?;
+        }
+
+        let missing = snapshots.missing_objects(&target);
+        if !missing.is_empty() {
+            for path in missing.iter().sorted() {
+                println!(
+                    "[ftp-deploy] No intact copy of '{}' is stored",
+                    path.display()
+                );
+            }
+            return Err(format!("deploy {} cannot be restored", target_id).into());
+        }
+
+        if self.dry || updates.is_empty() {
+            return Ok(());
+        }
+
+        let rollback_id = new_deploy_id();
+
+        let history_entry = |report| {
+            HistoryEntry::new(
+                &rollback_id,
+                "rollback",
+                started,
+                &base_path,
+                &creds,
+                report,
+            )
+        };
+
+        let mut report = UploadReport::default();
+        let uploaded = Uploader {
+            creds: &creds,
+            source: &snapshots,
+            force: self.force,
+            backup: Backup::new(config.backup().mode, &rollback_id, &base_path, &creds),
+            temp_upload: self.temp_upload,
+        }
+        .upload_files(
+            updates,
+            &mut files_tracking,
+            &mut remote_tracking,
+            &mut report,
+        );
+
+        if let Err(err) = uploaded {
+            let entry = history_entry(report).aborted(err.as_ref());
+            if let Err(history_err) =
+                History::new(&base_path).record(&entry, &creds, config.upload_history())
+            {
+                println!(
+                    "[ftp-deploy] Failed to record the failed rollback: {}",
+                    history_err
+                );
+            }
+            return Err(err);
+        }
+
+        files_tracking.write(&base_path)?;
+        remote_tracking.write(&base_path)?;
+
+        if let Some(shared) = &mut shared {
+            shared.store(&creds, &files_tracking, &remote_tracking)?;
+        }
+
+        if config.snapshots() > 0 {
+            snapshots.save(&rollback_id, &files_tracking, &snapshots)?;
+            snapshots.prune(config.snapshots())?;
+        }
+
+        if config.backup().mode != BackupMode::None {
+            let mut backups_tracking = BackupsTracking::load(&base_path)?;
+            backups_tracking.record(
+                BackupSet {
+                    id: rollback_id.clone(),
+                    mode: config.backup().mode,
+                    files: report.backed_up.clone(),
+                },
+                config.backup().keep,
+                &base_path,
+                &creds,
+            )?;
+            backups_tracking.write(&base_path)?;
+        }
+
+        History::new(&base_path).record(&history_entry(report), &creds, config.upload_history())?;
+
+        println!("[ftp-deploy] Rolled back to deploy {}.", target_id);
+
+        Ok(())
+    }
+}
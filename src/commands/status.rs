@@ -0,0 +1,163 @@
+use std::{path::PathBuf, process::ExitCode};
+
+use clap::Args;
+use indicatif::HumanBytes;
+use itertools::Itertools;
+
+use crate::{
+    config::{ConfigLoader, FtpConfig, IgnoredPolicy},
+    tracking::{
+        ArchiveFiles, FileMode, FileType, FileUpdate, FileUpdateType, FilesTracking, GitTree,
+        PathMatcher, TrackingFileLoder, apply_ignored_policy, collect_files,
+    },
+    upload::{FileSource, LocalFiles},
+};
+
+/// Exit code signaling that there are changes waiting to be deployed.
+const CHANGES_PENDING_EXIT_CODE: u8 = 2;
+
+#[derive(Args)]
+pub struct StatusCommand {
+    /// Directory to show the status of
+    #[arg(short, long)]
+    path: Option<PathBuf>,
+
+    /// Number of threads to use for walking files
+    #[arg(short, long)]
+    jobs: Option<usize>,
+
+    /// Show the changes a deploy of a .tar, .tar.gz or .zip archive would make
+    #[arg(long)]
+    from_archive: Option<PathBuf>,
+
+    /// Show the changes a deploy of a git commit, branch or tag would make
+    #[arg(long, conflicts_with = "from_archive")]
+    git_rev: Option<String>,
+}
+
+impl StatusCommand {
+    /// Shows the changes, the exit code tells whether there are any.
+    pub fn run(self) -> Result<ExitCode, Box<dyn std::error::Error>> {
+        let base_path = self.path.clone().unwrap_or_else(|| PathBuf::from("."));
+
+        let config = FtpConfig::load_or_create(&base_path)?;
+
+        // status never connects to the server, the local tracking is the shared one as of the
+        // last deploy from this directory
+        let files_tracking = FilesTracking::load(&base_path)?;
+        if config.shared_tracking() {
+            println!(
+                "[ftp-deploy] Comparing against the local copy of the shared tracking, it may be missing deploys made from elsewhere."
+            );
+        }
+        let mut archive = self.from_archive.as_ref().map(ArchiveFiles::new);
+        let mut tree = self
+            .git_rev
+            .as_ref()
+            .map(|rev| GitTree::new(&base_path, rev));
+        let mut files = if let Some(archive) = &mut archive {
+            archive.collect(&base_path, files_tracking, false, None)?
+        } else if let Some(tree) = &mut tree {
+            tree.collect(files_tracking, false, None)?
+        } else {
+            collect_files(
+                &base_path,
+                files_tracking,
+                self.jobs.unwrap_or_else(num_cpus::get),
+                false,
+                None,
+            )?
+        };
+        let source: &dyn FileSource = match (&archive, &tree) {
+            (Some(archive), _) => archive,
+            (_, Some(tree)) => tree,
+            _ => &LocalFiles,
+        };
+        if config.ignored() != IgnoredPolicy::Ask {
+            apply_ignored_policy(&mut files, config.ignored());
+        }
+
+        let protect = PathMatcher::new(&base_path, config.protect())?;
+        let updates = FileUpdate::detect_moves(FileUpdate::from_files(&files, &protect), &files);
+
+        let ignored = files
+            .iter()
+            .filter(|(_, (_, mode))| *mode == FileMode::Ignored)
+            .map(|(path, _)| path)
+            .sorted()
+            .collect_vec();
+        if !ignored.is_empty() {
+            println!("[ftp-deploy] Tracked files that are ignored now:");
+            for path in &ignored {
+                println!("\tignored:\t{}", path.display());
+            }
+        }
+
+        if updates.is_empty() && ignored.is_empty() {
+            println!("[ftp-deploy] Nothing to deploy, everything is up to date.");
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let mut upload_size = 0;
+        let mut labels = Vec::new();
+
+        if !updates.is_empty() {
+            println!("[ftp-deploy] Changes not deployed yet:");
+        }
+        for update in updates.iter().sorted_by(|a, b| a.file.cmp(&b.file)) {
+            let label = match (update.update_type, files.get(&update.file)) {
+                (FileUpdateType::Delete, _) => "deleted",
+                (FileUpdateType::Move, _) => "moved",
+                (_, Some((_, FileMode::Created))) => "created",
+                _ => "updated",
+            };
+            labels.push(label);
+
+            let size = match (update.update_type, update.file_type) {
+                (FileUpdateType::CreateOrUpdate, FileType::File) => {
+                    source.size(&update.file, &update.state)
+                }
+                _ => None,
+            };
+            upload_size += size.unwrap_or(0);
+
+            let suffix = match update.file_type {
+                FileType::Directory => "/",
+                FileType::File => "",
+            };
+
+            match size {
+                Some(size) => println!(
+                    "\t{}:\t{}{} ({})",
+                    label,
+                    update.file.display(),
+                    suffix,
+                    HumanBytes(size)
+                ),
+                None => match &update.from {
+                    Some(from) => println!(
+                        "\t{}:\t{}{} -> {}{}",
+                        label,
+                        from.display(),
+                        suffix,
+                        update.file.display(),
+                        suffix
+                    ),
+                    None => println!("\t{}:\t{}{}", label, update.file.display(), suffix),
+                },
+            }
+        }
+
+        println!(
+            "[ftp-deploy] {} file(s) created, {} file(s) updated, {} file(s) deleted, {} file(s) moved, {} file(s) ignored, {} to upload",
+            labels.iter().filter(|label| **label == "created").count(),
+            labels.iter().filter(|label| **label == "updated").count(),
+            labels.iter().filter(|label| **label == "deleted").count(),
+            labels.iter().filter(|label| **label == "moved").count(),
+            ignored.len(),
+            HumanBytes(upload_size)
+        );
+
+        Ok(ExitCode::from(CHANGES_PENDING_EXIT_CODE))
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+
+use clap::Args;
+use itertools::Itertools;
+
+use crate::{
+    commands::SubcommandDelegate,
+    config::{ConfigLoader, FtpConfig, FtpCreds},
+    ftp::{FtpStreamExt, RemoteEntry},
+    tracking::{
+        FileState, FilesTracking, GIT_FTP_LOG, IGNORE_FILE_NAME, PathMatcher, ReleasesTracking,
+        RemoteTracking, SharedTracking, TrackingFileLoder,
+    },
+};
+
+#[derive(Args)]
+pub struct VerifyCommand {
+    /// Directory to verify the deployment of
+    #[arg(short, long)]
+    path: Option<PathBuf>,
+
+    /// Also compare SHA-256 digests, downloading files if the server cannot compute them
+    #[arg(short, long)]
+    checksum: bool,
+}
+
+impl SubcommandDelegate for VerifyCommand {
+    fn run(self) -> Result<(), Box<dyn std::error::Error>> {
+        let base_path = self.path.unwrap_or_else(|| PathBuf::from("."));
+
+        let config = FtpConfig::load_or_create(&base_path)?;
+        let creds = FtpCreds::load_or_create(&base_path)?;
+        let mut files_tracking = FilesTracking::load(&base_path)?;
+        let mut remote_tracking = RemoteTracking::load(&base_path)?;
+        if config.shared_tracking() {
+            SharedTracking::load_into(&creds, &mut files_tracking, &mut remote_tracking)?;
+        }
+
+        // after an atomic deploy, the tracked files are those of the live release
+        let live = ReleasesTracking::load(&base_path)?.live_creds(&creds);
+
+        let root = live.remote_path(".");
+        println!(
+            "[ftp-deploy] Verifying {} tracked file(s) against {}{}",
+            files_tracking.files.len(),
+            creds.server,
+            root
+        );
+
+        // files ftp-deploy and git-ftp keep on the server for themselves are never tracked
+        let internal = creds.state_dir();
+        let git_ftp_log = creds.remote_path(GIT_FTP_LOG);
+
+        let mut ftp_stream = creds.open_stream()?;
+        let remote: HashMap<String, RemoteEntry> = ftp_stream
+            .list_recursive(&root)?
+            .into_iter()
+            .filter(|entry| !entry.is_within(&internal) && entry.path != git_ftp_log)
+            .map(|entry| (entry.path.clone(), entry))
+            .collect();
+
+        let mut tracked = HashSet::new();
+        let mut missing = Vec::new();
+        let mut mismatched = Vec::new();
+
+        for (path, state) in files_tracking.files.iter().sorted_by_key(|(path, _)| *path) {
+            let remote_path = live.remote_path(path);
+            tracked.insert(remote_path.clone());
+
+            if remote_path == root {
+                continue;
+            }
+
+            let Some(entry) = remote.get(&remote_path) else {
+                missing.push(path);
+                continue;
+            };
+
+            let hash = match (state, entry.is_dir) {
+                (FileState::Directory, true) => continue,
+                (FileState::Directory, false) => {
+                    mismatched.push((path, "expected a directory".to_string()));
+                    continue;
+                }
+                (FileState::File(_), true) => {
+                    mismatched.push((path, "expected a file".to_string()));
+                    continue;
+                }
+                (FileState::File(hash), false) => hash,
+            };
+
+            let deployed_size = remote_tracking.files.get(path).and_then(|state| state.size);
+            if let (Some(size), Some(deployed_size)) = (entry.size, deployed_size)
+                && size != deployed_size
+            {
+                mismatched.push((
+                    path,
+                    format!("size {} differs from deployed size {}", size, deployed_size),
+                ));
+                continue;
+            }
+
+            if self.checksum && ftp_stream.remote_sha256(&remote_path)? != *hash {
+                mismatched.push((path, "SHA-256 digest differs".to_string()));
+            }
+        }
+
+        // protected and ignored paths are left on the server on purpose, as are copies moved
+        // aside during an upload
+        let protect = PathMatcher::new(&base_path, config.protect())?;
+        let ignore = PathMatcher::from_file(&base_path, base_path.join(IGNORE_FILE_NAME))?;
+        let extra = remote
+            .values()
+            .filter(|entry| !tracked.contains(&entry.path) && !entry.is_aside())
+            .filter(|entry| {
+                let relative = PathBuf::from(entry.path[root.len()..].trim_start_matches('/'));
+                protect.matched(&relative, entry.is_dir).is_none()
+                    && ignore.matched(&relative, entry.is_dir).is_none()
+            })
+            .map(|entry| &entry.path)
+            .sorted()
+            .collect_vec();
+
+        for path in &missing {
+            println!("\tmissing:\t{}", path.display());
+        }
+        for (path, reason) in &mismatched {
+            println!("\tmismatched:\t{} ({})", path.display(), reason);
+        }
+        for path in &extra {
+            println!("\textra:\t\t{}", path);
+        }
+
+        println!(
+            "[ftp-deploy] {} missing, {} mismatched, {} extra file(s)",
+            missing.len(),
+            mismatched.len(),
+            extra.len()
+        );
+
+        if !missing.is_empty() || !mismatched.is_empty() || !extra.is_empty() {
+            return Err("remote does not match the tracking information".into());
+        }
+
+        println!("[ftp-deploy] Remote matches the tracking information.");
+
+        Ok(())
+    }
+}
@@ -1,28 +1,78 @@
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
-use ftp::FtpStream;
+use ftp::{FtpStream, types::FileType};
 use serde_derive::{Deserialize, Serialize};
 
-use crate::config::Config;
+use crate::{config::Config, tracking::REMOTE_DIR};
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct FtpCreds {
     pub server: String,
     pub base_path: PathBuf,
     pub username: String,
     pub password: String,
+    /// Remote directory ftp-deploy keeps its own data in, relative to `base_path` unless
+    /// absolute. Defaults to `.ftp-deploy`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_dir: Option<PathBuf>,
 }
 
 impl FtpCreds {
     pub fn open_stream(&self) -> Result<FtpStream, Box<dyn std::error::Error>> {
         let mut ftp_stream = FtpStream::connect(&self.server)?;
         ftp_stream.login(&self.username, &self.password)?;
+        // sizes reported by the server are only reliable in binary mode
+        ftp_stream.transfer_type(FileType::Binary)?;
         Ok(ftp_stream)
     }
 
     pub fn ftp_path(&self, path: impl AsRef<Path>) -> PathBuf {
         self.base_path.join(path)
     }
+
+    /// Absolute, normalized remote path of a local file, suitable for FTP commands.
+    pub fn remote_path(&self, path: impl AsRef<Path>) -> String {
+        format!("/{}", normalize(self.ftp_path(path)))
+    }
+
+    /// Absolute remote path of the directory ftp-deploy keeps its lock, history, backups and
+    /// shared tracking in.
+    pub fn state_dir(&self) -> String {
+        self.remote_path(self.state_dir.as_deref().unwrap_or(Path::new(REMOTE_DIR)))
+    }
+
+    /// Absolute remote path of `path` within the state directory.
+    pub fn state_path(&self, path: impl AsRef<Path>) -> String {
+        format!("{}/{}", self.state_dir(), normalize(path))
+    }
+
+    /// Whether the state directory lies below `base_path`, where the web server may serve it.
+    pub fn state_dir_is_public(&self) -> bool {
+        let base = self.remote_path(".");
+        self.state_dir()
+            .starts_with(&format!("{}/", base.trim_end_matches('/')))
+    }
+
+    /// Normalized remote path of a local file relative to `base_path`.
+    pub fn relative_remote_path(&self, path: impl AsRef<Path>) -> String {
+        normalize(path)
+    }
+}
+
+fn normalize(path: impl AsRef<Path>) -> String {
+    let mut components = Vec::new();
+
+    for component in path.as_ref().components() {
+        match component {
+            Component::Normal(name) => components.push(name.to_string_lossy().into_owned()),
+            Component::ParentDir => {
+                components.pop();
+            }
+            _ => {}
+        }
+    }
+
+    components.join("/")
 }
 
 impl Config for FtpCreds {
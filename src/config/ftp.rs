@@ -1,13 +1,187 @@
 #[cfg(unix)]
 use std::process::Command;
+use std::{collections::HashMap, path::PathBuf};
 
 use serde_derive::{Deserialize, Serialize};
 
-use crate::config::Config;
+use crate::{
+    config::{Config, FtpCreds},
+    tracking::{FileMode, FileState, FileType, FileUpdate, FileUpdateType, IGNORE_FILE_NAME},
+};
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+/// Below this number of tracked files the percentage of deletions is not checked, as deleting
+/// one of a handful of files is not unusual.
+const MASS_DELETE_MIN_TRACKED: usize = 10;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackupMode {
+    /// Overwrite and delete remote files without a backup
+    #[default]
+    None,
+    /// Move remote files into `.ftp-deploy/backups/<id>` on the server
+    Remote,
+    /// Download remote files into `.ftp/backups/<id>`
+    Local,
+}
+
+/// What happens to deployed files that are excluded by an ignore file later on.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IgnoredPolicy {
+    /// Keep them on the server and stop tracking them
+    #[default]
+    Keep,
+    /// Delete them from the server
+    Delete,
+    /// Ask for every file, keeping it if there is no terminal
+    Ask,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackupConfig {
+    pub mode: BackupMode,
+    /// Number of backups to keep
+    pub keep: usize,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            mode: BackupMode::None,
+            keep: 5,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MassDeleteConfig {
+    /// Maximum number of files a single deploy may delete, unlimited if unset
+    pub max_count: Option<usize>,
+    /// Maximum percentage of the tracked files a single deploy may delete
+    pub max_percent: f64,
+}
+
+impl Default for MassDeleteConfig {
+    fn default() -> Self {
+        Self {
+            max_count: None,
+            max_percent: 50.0,
+        }
+    }
+}
+
+impl MassDeleteConfig {
+    /// Refuses changes that delete most of the site, as they usually come from a wrong path or
+    /// a build that did not produce any output.
+    pub fn check(
+        &self,
+        files: &HashMap<PathBuf, (FileState, FileMode)>,
+        updates: &[FileUpdate],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let tracked = files
+            .values()
+            .filter(|(state, mode)| *state != FileState::Directory && *mode != FileMode::Created)
+            .count();
+        // moved files are not lost, only the deletions that are actually planned count
+        let deleted = updates
+            .iter()
+            .filter(|update| {
+                update.update_type == FileUpdateType::Delete && update.file_type == FileType::File
+            })
+            .count();
+        // the configuration files live next to the deployed files, they do not make a site
+        let remaining = files
+            .iter()
+            .filter(|(path, (state, mode))| {
+                *state != FileState::Directory
+                    && *mode != FileMode::Deleted
+                    && !path.file_name().is_some_and(|name| {
+                        [FtpConfig::FILE_NAME, FtpCreds::FILE_NAME, IGNORE_FILE_NAME]
+                            .iter()
+                            .any(|file_name| name == *file_name)
+                    })
+            })
+            .count();
+
+        if deleted == 0 {
+            return Ok(());
+        }
+
+        let reason = if remaining == 0 {
+            Some(format!(
+                "the source tree is empty, {} tracked file(s) would be deleted",
+                deleted
+            ))
+        } else if self.max_count.is_some_and(|max_count| deleted > max_count) {
+            Some(format!(
+                "{} file(s) would be deleted, more than the configured maximum of {}",
+                deleted,
+                self.max_count.unwrap()
+            ))
+        } else if tracked >= MASS_DELETE_MIN_TRACKED
+            && deleted as f64 * 100.0 / tracked as f64 > self.max_percent
+        {
+            Some(format!(
+                "{} of {} tracked file(s) would be deleted, more than the configured maximum of {}%",
+                deleted, tracked, self.max_percent
+            ))
+        } else {
+            None
+        };
+
+        match reason {
+            Some(reason) => Err(format!(
+                "aborting: {}. Check the path and the build output, or pass --allow-mass-delete if this is intended",
+                reason
+            )
+            .into()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct FtpConfig {
     hooks: Vec<String>,
+    /// Number of deploys kept in `.ftp/deploys` to roll back to, 0 disables them
+    snapshots: usize,
+    backup: BackupConfig,
+    /// Upload a copy of `.ftp/history.jsonl` to the server after every deploy
+    upload_history: bool,
+    /// Seconds after which the lock of a deploy that never finished is considered stale
+    lock_ttl: u64,
+    /// Keep the files tracking in `.ftp-deploy/files.json` on the server, shared by everyone
+    shared_tracking: bool,
+    mass_delete: MassDeleteConfig,
+    /// Patterns of remote paths that are never created, overwritten or deleted
+    protect: Vec<String>,
+    ignored: IgnoredPolicy,
+    /// Refuse to deploy while the git working tree has uncommitted changes
+    require_clean: bool,
+    /// Write the deployed commit to `.git-ftp.log` on the server, like git-ftp does
+    git_ftp_log: bool,
+}
+
+impl Default for FtpConfig {
+    fn default() -> Self {
+        Self {
+            hooks: Vec::new(),
+            snapshots: 10,
+            backup: BackupConfig::default(),
+            upload_history: false,
+            lock_ttl: 1800,
+            shared_tracking: false,
+            mass_delete: MassDeleteConfig::default(),
+            protect: Vec::new(),
+            ignored: IgnoredPolicy::Keep,
+            require_clean: false,
+            git_ftp_log: false,
+        }
+    }
 }
 
 impl FtpConfig {
@@ -15,6 +189,46 @@ impl FtpConfig {
         &self.hooks
     }
 
+    pub fn snapshots(&self) -> usize {
+        self.snapshots
+    }
+
+    pub fn backup(&self) -> &BackupConfig {
+        &self.backup
+    }
+
+    pub fn upload_history(&self) -> bool {
+        self.upload_history
+    }
+
+    pub fn lock_ttl(&self) -> u64 {
+        self.lock_ttl
+    }
+
+    pub fn shared_tracking(&self) -> bool {
+        self.shared_tracking
+    }
+
+    pub fn mass_delete(&self) -> &MassDeleteConfig {
+        &self.mass_delete
+    }
+
+    pub fn protect(&self) -> &[String] {
+        &self.protect
+    }
+
+    pub fn ignored(&self) -> IgnoredPolicy {
+        self.ignored
+    }
+
+    pub fn require_clean(&self) -> bool {
+        self.require_clean
+    }
+
+    pub fn git_ftp_log(&self) -> bool {
+        self.git_ftp_log
+    }
+
     pub fn run_hooks(&self) {
         for hook in &self.hooks {
             println!("[ftp-deploy] Running hook: \"{}\"", hook);
@@ -53,3 +267,91 @@ impl FtpConfig {
 impl Config for FtpConfig {
     const FILE_NAME: &'static str = "ftp-deploy.json";
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracking::PathMatcher;
+
+    /// `kept` unchanged and `deleted` deleted tracked files.
+    fn files(kept: usize, deleted: usize) -> HashMap<PathBuf, (FileState, FileMode)> {
+        (0..kept)
+            .map(|i| (format!("./kept{}", i), FileMode::Untouched))
+            .chain((0..deleted).map(|i| (format!("./deleted{}", i), FileMode::Deleted)))
+            .map(|(path, mode)| (PathBuf::from(path), (FileState::File("0".repeat(64)), mode)))
+            .collect()
+    }
+
+    fn check(config: &MassDeleteConfig, files: &HashMap<PathBuf, (FileState, FileMode)>) -> bool {
+        let updates = FileUpdate::from_files(files, &PathMatcher::new(".", &[]).unwrap());
+        config.check(files, &updates).is_ok()
+    }
+
+    #[test]
+    fn refuses_deleting_more_than_the_percentage() {
+        let config = MassDeleteConfig::default();
+
+        assert!(check(&config, &files(10, 10)));
+        assert!(!check(&config, &files(9, 11)));
+        assert!(check(
+            &MassDeleteConfig {
+                max_percent: 60.0,
+                ..config
+            },
+            &files(9, 11)
+        ));
+    }
+
+    #[test]
+    fn ignores_the_percentage_of_a_few_files() {
+        let config = MassDeleteConfig::default();
+
+        assert!(check(&config, &files(2, 7)));
+        assert!(!check(&config, &files(2, 8)));
+    }
+
+    #[test]
+    fn refuses_deleting_more_than_the_count() {
+        let config = MassDeleteConfig {
+            max_count: Some(3),
+            ..MassDeleteConfig::default()
+        };
+
+        assert!(check(&config, &files(20, 3)));
+        assert!(!check(&config, &files(20, 4)));
+    }
+
+    #[test]
+    fn refuses_an_empty_source_tree() {
+        let config = MassDeleteConfig {
+            max_percent: 100.0,
+            ..MassDeleteConfig::default()
+        };
+        let mut files = files(0, 2);
+        files.insert(
+            PathBuf::from(format!("./{}", FtpConfig::FILE_NAME)),
+            (FileState::File("1".repeat(64)), FileMode::Untouched),
+        );
+
+        assert!(!check(&config, &files));
+        assert!(check(&config, &self::files(0, 0)));
+    }
+
+    #[test]
+    fn moved_files_are_not_deletions() {
+        let config = MassDeleteConfig::default();
+        let mut files = files(2, 8);
+        for i in 0..8 {
+            files.insert(
+                PathBuf::from(format!("./moved{}", i)),
+                (FileState::File("0".repeat(64)), FileMode::Created),
+            );
+        }
+        let updates = FileUpdate::detect_moves(
+            FileUpdate::from_files(&files, &PathMatcher::new(".", &[]).unwrap()),
+            &files,
+        );
+
+        assert!(config.check(&files, &updates).is_ok());
+    }
+}
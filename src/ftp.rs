@@ -1,12 +1,145 @@
-use std::path::{Component, Path};
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream},
+    path::{Component, Path},
+};
 
-use ftp::{FtpError, FtpStream};
+use ftp::{FtpError, FtpStream, status, types::Line};
+use sha2::{Digest, Sha256};
+
+use crate::tracking::RemoteFileState;
+
+/// Suffix of the name a file is moved aside to while it is replaced. These are never removed
+/// automatically, they may be the only copy left.
+const ASIDE_SUFFIX: &str = ".ftp-deploy-old";
+
+/// Path `path` is moved aside to while it is replaced, hidden next to it.
+fn aside_path(path: &str) -> String {
+    match path.rsplit_once('/') {
+        Some((directory, name)) => format!("{}/.{}{}", directory, name, ASIDE_SUFFIX),
+        None => format!(".{}{}", path, ASIDE_SUFFIX),
+    }
+}
+
+/// An entry of a remote directory listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteEntry {
+    /// Absolute remote path
+    pub path: String,
+    pub is_dir: bool,
+    pub size: Option<usize>,
+}
+
+impl RemoteEntry {
+    /// Whether the entry is `directory` itself or lies somewhere below it.
+    pub fn is_within(&self, directory: &str) -> bool {
+        self.path
+            .strip_prefix(directory)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+
+    /// Whether the entry was moved aside while it was replaced, or lies below such a directory.
+    pub fn is_aside(&self) -> bool {
+        self.path
+            .split('/')
+            .any(|name| name.starts_with('.') && name.ends_with(ASIDE_SUFFIX))
+    }
+}
+
+/// Splits off the first `count` whitespace separated fields of `line`, returning them together
+/// with the (untrimmed) remainder.
+fn split_fields(line: &str, count: usize) -> Option<(Vec<&str>, &str)> {
+    let mut rest = line;
+    let mut fields = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        rest = rest.trim_start();
+        let end = rest.find(char::is_whitespace)?;
+        fields.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+
+    Some((fields, rest.trim_start()))
+}
+
+/// Parses a line of a `LIST` response in either Unix (`ls -l`) or DOS format.
+/// Returns the entry name, whether it is a directory and its size.
+fn parse_list_line(line: &str) -> Option<(String, bool, Option<usize>)> {
+    let first = line.chars().next()?;
+
+    if first.is_ascii_digit() {
+        // 01-31-24  10:15AM       <DIR>          name
+        let (fields, name) = split_fields(line, 3)?;
+
+        return Some(if fields[2] == "<DIR>" {
+            (name.to_string(), true, None)
+        } else {
+            (name.to_string(), false, fields[2].parse().ok())
+        });
+    }
+
+    // drwxr-xr-x 2 owner group 4096 Jan 31 10:15 name
+    let (fields, mut name) = split_fields(line, 8)?;
+    if first == 'l' {
+        name = name.split(" -> ").next()?;
+    }
+
+    Some((name.to_string(), first == 'd', fields[4].parse().ok()))
+}
+
+/// Parses the address of a `227 Entering Passive Mode (h1,h2,h3,h4,p1,p2)` reply.
+fn parse_pasv_reply(reply: &str) -> Option<SocketAddr> {
+    let start = reply.find('(')? + 1;
+    let end = start + reply[start..].find(')')?;
+    let numbers: Vec<u8> = reply[start..end]
+        .split(',')
+        .map(|number| number.trim().parse().ok())
+        .collect::<Option<_>>()?;
+
+    match numbers[..] {
+        [a, b, c, d, high, low] => Some(SocketAddr::from((
+            [a, b, c, d],
+            u16::from(high) << 8 | u16::from(low),
+        ))),
+        _ => None,
+    }
+}
 
 pub trait FtpStreamExt {
     fn cwd_or_create_recursive(
         &mut self,
         directory: Option<impl AsRef<Path>>,
     ) -> Result<(), FtpError>;
+
+    /// Returns the size and modification time of a remote file, or `None` if it does not exist.
+    fn remote_state(&mut self, path: &str) -> Result<Option<RemoteFileState>, FtpError>;
+
+    /// Sends a raw command the ftp crate has no method for and reads the response.
+    fn quote(&mut self, command: &str, expected_code: &[u32]) -> Result<Line, FtpError>;
+
+    /// Lists `directory` with `LIST -a`, including hidden files. Unlike the ftp crate, the data
+    /// connection is opened before the command is sent, so a server refusing `-a` leaves the
+    /// control connection usable.
+    fn list_all(&mut self, directory: &str) -> Result<Vec<String>, FtpError>;
+
+    /// Recursively lists all files and directories below `directory`.
+    fn list_recursive(&mut self, directory: &str) -> Result<Vec<RemoteEntry>, FtpError>;
+
+    /// Computes the SHA-256 digest of a remote file, using the `HASH` or `XSHA256` extension if
+    /// the server supports it and downloading the file otherwise.
+    fn remote_sha256(&mut self, path: &str) -> Result<String, FtpError>;
+
+    /// Downloads a remote file to `destination`, overwriting it if it exists.
+    fn download(&mut self, path: &str, destination: &Path) -> Result<(), FtpError>;
+
+    /// Deletes a remote directory including everything below it.
+    fn remove_recursive(&mut self, directory: &str) -> Result<(), FtpError>;
+
+    /// Renames `from` onto `to`, replacing it. On servers that refuse to rename onto an existing
+    /// file, `to` is moved aside first and put back if the rename still fails. `from` is left in
+    /// place on failure.
+    fn rename_over(&mut self, from: &str, to: &str) -> Result<(), FtpError>;
 }
 
 impl FtpStreamExt for FtpStream {
@@ -35,4 +168,270 @@ impl FtpStreamExt for FtpStream {
 
         Ok(())
     }
+
+    fn remote_state(&mut self, path: &str) -> Result<Option<RemoteFileState>, FtpError> {
+        // only 550 means the file does not exist, other errors must not be mistaken for that
+        let size = match self.quote(
+            &format!("SIZE {}", path),
+            &[status::FILE, status::FILE_UNAVAILABLE],
+        )? {
+            Line(status::FILE, message) => message
+                .split_whitespace()
+                .last()
+                .and_then(|size| size.parse().ok()),
+            _ => return Ok(None),
+        };
+
+        let modified = match self.mdtm(path) {
+            Ok(modified) => modified.map(|modified| modified.timestamp()),
+            Err(FtpError::InvalidResponse(_)) => None,
+            Err(err) => return Err(err),
+        };
+
+        Ok(Some(RemoteFileState { size, modified }))
+    }
+
+    fn quote(&mut self, command: &str, expected_code: &[u32]) -> Result<Line, FtpError> {
+        let mut stream: &TcpStream = self.get_ref();
+        stream
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .map_err(FtpError::ConnectionError)?;
+
+        self.read_response_in(expected_code)
+    }
+
+    fn list_all(&mut self, directory: &str) -> Result<Vec<String>, FtpError> {
+        let Line(_, reply) = self.quote("PASV", &[status::PASSIVE_MODE])?;
+        let address = parse_pasv_reply(&reply).ok_or_else(|| {
+            FtpError::InvalidResponse(format!("Invalid PASV response: {}", reply))
+        })?;
+        let mut data_stream = TcpStream::connect(address).map_err(FtpError::ConnectionError)?;
+
+        self.quote(
+            &format!("LIST -a {}", directory),
+            &[status::ABOUT_TO_SEND, status::ALREADY_OPEN],
+        )?;
+
+        let mut listing = String::new();
+        data_stream
+            .read_to_string(&mut listing)
+            .map_err(FtpError::ConnectionError)?;
+        drop(data_stream);
+
+        self.read_response_in(&[
+            status::CLOSING_DATA_CONNECTION,
+            status::REQUESTED_FILE_ACTION_OK,
+        ])?;
+
+        Ok(listing
+            .lines()
+            .map(|line| line.trim_end_matches('\r').to_string())
+            .filter(|line| !line.is_empty())
+            .collect())
+    }
+
+    fn list_recursive(&mut self, directory: &str) -> Result<Vec<RemoteEntry>, FtpError> {
+        let mut entries = Vec::new();
+        let mut pending = vec![directory.trim_end_matches('/').to_string()];
+        // most servers hide dotfiles such as .htaccess unless asked for them with `-a`
+        let mut list_all = true;
+
+        while let Some(directory) = pending.pop() {
+            let directory_path = if directory.is_empty() {
+                "/"
+            } else {
+                &directory
+            };
+
+            let lines = if list_all {
+                match self.list_all(directory_path) {
+                    Ok(lines) if !lines.is_empty() => lines,
+                    // a server that takes `-a` for a path lists nothing or fails
+                    Ok(_) | Err(FtpError::InvalidResponse(_)) => {
+                        let lines = self.list(Some(directory_path))?;
+                        list_all = lines.is_empty();
+                        lines
+                    }
+                    Err(err) => return Err(err),
+                }
+            } else {
+                self.list(Some(directory_path))?
+            };
+
+            for (name, is_dir, size) in lines.iter().filter_map(|line| parse_list_line(line)) {
+                if name == "." || name == ".." {
+                    continue;
+                }
+
+                let path = format!("{}/{}", directory, name);
+                if is_dir {
+                    pending.push(path.clone());
+                }
+
+                entries.push(RemoteEntry { path, is_dir, size });
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn remote_sha256(&mut self, path: &str) -> Result<String, FtpError> {
+        let parse_digest = |Line(_, message): Line| {
+            message
+                .split_whitespace()
+                .find(|word| word.len() == 64 && word.chars().all(|c| c.is_ascii_hexdigit()))
+                .map(|digest| digest.to_lowercase())
+        };
+
+        if self
+            .quote("OPTS HASH SHA-256", &[status::COMMAND_OK])
+            .is_ok()
+            && let Some(digest) = self
+                .quote(&format!("HASH {}", path), &[status::FILE])
+                .ok()
+                .and_then(parse_digest)
+        {
+            return Ok(digest);
+        }
+
+        if let Some(digest) = self
+            .quote(
+                &format!("XSHA256 {}", path),
+                &[status::FILE, status::COMMAND_OK],
+            )
+            .ok()
+            .and_then(parse_digest)
+        {
+            return Ok(digest);
+        }
+
+        self.retr(path, |reader| {
+            let mut hasher = Sha256::new();
+            io::copy(reader, &mut hasher).map_err(FtpError::ConnectionError)?;
+            Ok(format!("{:x}", hasher.finalize()))
+        })
+    }
+
+    fn download(&mut self, path: &str, destination: &Path) -> Result<(), FtpError> {
+        self.retr(path, |reader| {
+            let mut file = File::create(destination).map_err(FtpError::ConnectionError)?;
+            io::copy(reader, &mut file).map_err(FtpError::ConnectionError)?;
+            Ok(())
+        })
+    }
+
+    fn remove_recursive(&mut self, directory: &str) -> Result<(), FtpError> {
+        let mut entries = self.list_recursive(directory)?;
+
+        // deepest entries first, so directories are empty once they are removed
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.path.matches('/').count()));
+
+        for entry in entries {
+            if entry.is_dir {
+                self.rmdir(&entry.path)?;
+            } else {
+                self.rm(&entry.path)?;
+            }
+        }
+
+        self.rmdir(directory)
+    }
+
+    fn rename_over(&mut self, from: &str, to: &str) -> Result<(), FtpError> {
+        let Err(err) = self.rename(from, to) else {
+            return Ok(());
+        };
+
+        let aside = aside_path(to);
+        if self.rename(to, &aside).is_err() {
+            return Err(err);
+        }
+
+        if let Err(err) = self.rename(from, to) {
+            if self.rename(&aside, to).is_ok() {
+                return Err(err);
+            }
+            return Err(FtpError::InvalidResponse(format!(
+                "failed to rename '{}' onto '{}' ({}), the previous version is left at '{}'",
+                from, to, err, aside
+            )));
+        }
+
+        let _ = self.rm(&aside);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_unix_list_lines() {
+        assert_eq!(
+            parse_list_line("-rw-r--r--   1 owner group     1234 Jan 31 10:15 index.html"),
+            Some(("index.html".to_string(), false, Some(1234)))
+        );
+        assert_eq!(
+            parse_list_line("drwxr-xr-x   2 owner group     4096 Jan 31  2023 assets"),
+            Some(("assets".to_string(), true, Some(4096)))
+        );
+        assert_eq!(parse_list_line("total 12"), None);
+    }
+
+    #[test]
+    fn keeps_spaces_in_names() {
+        assert_eq!(
+            parse_list_line("-rw-r--r-- 1 owner group 7 Jan 31 10:15 my  report .pdf"),
+            Some(("my  report .pdf".to_string(), false, Some(7)))
+        );
+        assert_eq!(
+            parse_list_line("01-31-24  10:15AM       <DIR>          old files"),
+            Some(("old files".to_string(), true, None))
+        );
+    }
+
+    #[test]
+    fn parses_dos_list_lines() {
+        assert_eq!(
+            parse_list_line("01-31-24  10:15AM                 1234 index.html"),
+            Some(("index.html".to_string(), false, Some(1234)))
+        );
+        assert_eq!(
+            parse_list_line("01-31-24  10:15AM       <DIR>          assets"),
+            Some(("assets".to_string(), true, None))
+        );
+    }
+
+    #[test]
+    fn strips_symlink_targets() {
+        assert_eq!(
+            parse_list_line("lrwxrwxrwx 1 owner group 11 Jan 31 10:15 current -> releases/1"),
+            Some(("current".to_string(), false, Some(11)))
+        );
+    }
+
+    #[test]
+    fn parses_pasv_replies() {
+        assert_eq!(
+            parse_pasv_reply("227 Entering Passive Mode (127,0,0,1,195,80)."),
+            Some(SocketAddr::from(([127, 0, 0, 1], 50000)))
+        );
+        assert_eq!(parse_pasv_reply("227 Entering Passive Mode"), None);
+        assert_eq!(parse_pasv_reply("227 (127,0,0,1,300,80)"), None);
+    }
+
+    #[test]
+    fn detects_aside_paths() {
+        let entry = |path: &str| RemoteEntry {
+            path: path.to_string(),
+            is_dir: false,
+            size: None,
+        };
+
+        assert!(entry(&aside_path("/www/index.html")).is_aside());
+        assert!(entry("/www/.assets.ftp-deploy-old/app.js").is_aside());
+        assert!(!entry("/www/index.html").is_aside());
+        assert!(!entry("/www/notes.ftp-deploy-old").is_aside());
+    }
 }
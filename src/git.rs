@@ -0,0 +1,234 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+};
+
+use sha2::{Digest, Sha256};
+
+/// Runs `git` in `path` and returns its output, or the error it printed.
+fn git(path: &Path, args: &[&str]) -> Result<String, Box<dyn std::error::Error>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(path)
+        .args(args)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Commit checked out in the git repository containing `path`, if any.
+pub fn head_commit(path: impl AsRef<Path>) -> Option<String> {
+    resolve_commit(path, "HEAD")
+}
+
+/// Commit a branch, tag or abbreviated commit `rev` points to, if it exists.
+pub fn resolve_commit(path: impl AsRef<Path>, rev: &str) -> Option<String> {
+    git(
+        path.as_ref(),
+        &[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("{}^{{commit}}", rev),
+        ],
+    )
+    .ok()
+    .map(|output| output.trim().to_string())
+}
+
+/// Whether there are uncommitted or untracked files below `path`, apart from the ones in
+/// `exclude`. `None` if `path` is not inside a git repository.
+pub fn is_dirty(path: impl AsRef<Path>, exclude: &[&str]) -> Option<bool> {
+    let mut args = vec!["status", "--porcelain", "--", "."];
+    let exclude = exclude
+        .iter()
+        .map(|path| format!(":(exclude){}", path))
+        .collect::<Vec<_>>();
+    args.extend(exclude.iter().map(String::as_str));
+
+    git(path.as_ref(), &args)
+        .ok()
+        .map(|output| !output.trim().is_empty())
+}
+
+/// Files below `path` that differ between `rev` and the working tree, including untracked ones,
+/// relative to `path`. Deleted files are listed as well.
+pub fn changed_since(
+    path: impl AsRef<Path>,
+    rev: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let path = path.as_ref();
+    let changed = git(
+        path,
+        &[
+            "diff",
+            "--name-only",
+            "-z",
+            "--no-renames",
+            "--relative",
+            rev,
+            "--",
+        ],
+    )?;
+    let untracked = git(path, &["ls-files", "-z", "--others", "--exclude-standard"])?;
+
+    Ok(changed
+        .split('\0')
+        .chain(untracked.split('\0'))
+        .filter(|file| !file.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Streams the contents of the given blobs through `f` in one go with `git cat-file --batch`.
+/// Whatever `f` does not read of a blob is skipped.
+pub fn cat_blobs(
+    path: &Path,
+    blobs: &[&str],
+    mut f: impl FnMut(&str, &mut dyn Read) -> io::Result<()>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(path)
+        .args(["cat-file", "--batch"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // written from another thread, git blocks once its output is not read
+    let input = blobs
+        .iter()
+        .map(|blob| format!("{}\n", blob))
+        .collect::<String>();
+    let mut stdin = child.stdin.take().ok_or("failed to open stdin of git")?;
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    let mut reader = BufReader::new(child.stdout.take().ok_or("failed to open stdout of git")?);
+
+    for blob in blobs {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+
+        let size: u64 = match header.split_whitespace().collect::<Vec<_>>()[..] {
+            [_, "blob", size] => size.parse()?,
+            _ => return Err(format!("failed to read blob {}: {}", blob, header.trim()).into()),
+        };
+
+        let mut contents = (&mut reader).take(size);
+        f(blob, &mut contents)?;
+        io::copy(&mut contents, &mut io::sink())?;
+        // every object is followed by a newline
+        reader.read_exact(&mut [0])?;
+    }
+
+    writer.join().map_err(|_| "failed to write to git")??;
+    child.wait()?;
+
+    Ok(())
+}
+
+/// SHA-256 digests of the given blobs, read in one go with `git cat-file --batch`.
+pub fn blob_sha256s(
+    path: &Path,
+    blobs: &[&str],
+) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+    let mut digests = HashMap::new();
+
+    cat_blobs(path, blobs, |blob, contents| {
+        let mut hasher = Sha256::new();
+        io::copy(contents, &mut hasher)?;
+        digests.insert(blob.to_string(), format!("{:x}", hasher.finalize()));
+        Ok(())
+    })?;
+
+    Ok(digests)
+}
+
+/// A file in the tree of a commit.
+pub struct TreeFile {
+    /// Path relative to the directory the tree was listed for
+    pub path: String,
+    pub blob: String,
+    pub size: u64,
+}
+
+/// Files below `path` in the tree of `rev`.
+pub fn tree_files(
+    path: impl AsRef<Path>,
+    rev: &str,
+) -> Result<Vec<TreeFile>, Box<dyn std::error::Error>> {
+    let tree = git(
+        path.as_ref(),
+        &["ls-tree", "-r", "-l", "-z", rev, "--", "."],
+    )?;
+
+    // <mode> SP <type> SP <object> SP+ <size> TAB <file>
+    Ok(tree
+        .split('\0')
+        .filter_map(|line| {
+            let (info, file) = line.split_once('\t')?;
+            match info.split_whitespace().collect::<Vec<_>>()[..] {
+                [_, "blob", blob, size] => Some(TreeFile {
+                    path: file.to_string(),
+                    blob: blob.to_string(),
+                    size: size.parse().ok()?,
+                }),
+                _ => None,
+            }
+        })
+        .collect())
+}
+
+/// Files below `path` in the tree of `rev`, relative to `path`, with the SHA-256 digests of
+/// their contents.
+pub fn tree_sha256s(
+    path: impl AsRef<Path>,
+    rev: &str,
+) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+    let path = path.as_ref();
+    let files = tree_files(path, rev)?;
+
+    let mut blobs = files
+        .iter()
+        .map(|file| file.blob.as_str())
+        .collect::<Vec<_>>();
+    blobs.sort_unstable();
+    blobs.dedup();
+    let digests = blob_sha256s(path, &blobs)?;
+
+    Ok(files
+        .iter()
+        .map(|file| (file.path.clone(), digests[&file.blob].clone()))
+        .collect())
+}
+
+/// Contents of a blob.
+pub fn read_blob(path: impl AsRef<Path>, blob: &str) -> io::Result<Vec<u8>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(path.as_ref())
+        .args(["cat-file", "blob", blob])
+        .output()?;
+
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "failed to read blob {}: {}",
+            blob,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(output.stdout)
+}
@@ -0,0 +1,258 @@
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Cursor},
+    path::{Path, PathBuf},
+    process,
+};
+
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use ftp::FtpStream;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    config::FtpCreds,
+    ftp::FtpStreamExt,
+    tracking::{HistoryEntry, TRACKING_DIR},
+};
+
+const REMOTE_LOCK_FILE: &str = "lock.json";
+const LOCAL_LOCK_FILE: &str = "deploy.lock";
+
+/// Keeps Apache from serving the state directory when it lies inside the web root.
+const DENY_FILE: &str = ".htaccess";
+const DENY_RULES: &str = "<IfModule mod_authz_core.c>
+    Require all denied
+</IfModule>
+<IfModule !mod_authz_core.c>
+    Order allow,deny
+    Deny from all
+</IfModule>
+";
+
+/// Marks a deploy in progress. Stored in `lock.json` in the state directory on the server against other
+/// machines and in `.ftp/deploy.lock` against concurrent runs on the same machine.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeployLock {
+    pub owner: String,
+    pub host: String,
+    pub pid: u32,
+    /// RFC 3339 UTC time the lock was taken
+    pub started: String,
+    /// Seconds after which the lock is considered stale
+    pub ttl: u64,
+}
+
+impl DeployLock {
+    pub fn new(ttl: u64) -> Self {
+        Self {
+            owner: HistoryEntry::current_user(),
+            host: HistoryEntry::current_host(),
+            pid: process::id(),
+            started: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            ttl,
+        }
+    }
+
+    pub fn expires(&self) -> Option<DateTime<Utc>> {
+        let started = DateTime::parse_from_rfc3339(&self.started).ok()?;
+        Some(started.with_timezone(&Utc) + Duration::seconds(self.ttl as i64))
+    }
+
+    /// Whether the lock still blocks other deploys. Locks with an unreadable start time are
+    /// treated as live, they can still be broken manually.
+    pub fn is_live(&self) -> bool {
+        self.expires().is_none_or(|expires| expires > Utc::now())
+    }
+}
+
+impl fmt::Display for DeployLock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}@{} (pid {}) since {}, ",
+            self.owner, self.host, self.pid, self.started
+        )?;
+
+        match self.expires() {
+            Some(expires) if self.is_live() => write!(
+                f,
+                "expires {}",
+                expires.to_rfc3339_opts(SecondsFormat::Secs, true)
+            ),
+            Some(_) => write!(f, "expired"),
+            None => write!(f, "never expires"),
+        }
+    }
+}
+
+pub fn remote_lock_path(creds: &FtpCreds) -> String {
+    creds.state_path(REMOTE_LOCK_FILE)
+}
+
+pub fn local_lock_path(base_path: impl AsRef<Path>) -> PathBuf {
+    base_path.as_ref().join(TRACKING_DIR).join(LOCAL_LOCK_FILE)
+}
+
+fn read_remote_with(
+    ftp_stream: &mut FtpStream,
+    path: &str,
+) -> Result<Option<DeployLock>, Box<dyn std::error::Error>> {
+    if ftp_stream.remote_state(path)?.is_none() {
+        return Ok(None);
+    }
+
+    let contents = ftp_stream.simple_retr(path)?;
+    Ok(Some(serde_json::from_reader(contents)?))
+}
+
+/// Reads the lock on the server, if there is one.
+pub fn read_remote(creds: &FtpCreds) -> Result<Option<DeployLock>, Box<dyn std::error::Error>> {
+    read_remote_with(&mut creds.open_stream()?, &remote_lock_path(creds))
+}
+
+/// Reads the lock of this machine, if there is one.
+pub fn read_local(
+    base_path: impl AsRef<Path>,
+) -> Result<Option<DeployLock>, Box<dyn std::error::Error>> {
+    match File::open(local_lock_path(base_path)) {
+        Ok(file) => Ok(Some(serde_json::from_reader(file)?)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+pub fn remove_remote(creds: &FtpCreds) -> Result<(), Box<dyn std::error::Error>> {
+    creds.open_stream()?.rm(&remote_lock_path(creds))?;
+    Ok(())
+}
+
+pub fn remove_local(base_path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
+    match fs::remove_file(local_lock_path(base_path)) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+fn acquire_local(base_path: &Path, lock: &DeployLock) -> Result<(), Box<dyn std::error::Error>> {
+    let path = local_lock_path(base_path);
+    fs::create_dir_all(path.parent().unwrap())?;
+
+    // creating the file fails if it exists, so only one run can win
+    let file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => match read_local(base_path)? {
+            Some(existing) if existing.is_live() => {
+                return Err(
+                    format!("another deploy is running on this machine: {}", existing).into(),
+                );
+            }
+            _ => {
+                fs::remove_file(&path)?;
+                OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)?
+            }
+        },
+        Err(err) => return Err(err.into()),
+    };
+
+    serde_json::to_writer(file, lock)?;
+    Ok(())
+}
+
+fn acquire_remote(creds: &FtpCreds, lock: &DeployLock) -> Result<(), Box<dyn std::error::Error>> {
+    let path = remote_lock_path(creds);
+    let mut ftp_stream = creds.open_stream()?;
+
+    match read_remote_with(&mut ftp_stream, &path)? {
+        Some(existing) if existing.is_live() => {
+            return Err(format!(
+                "the server is locked by {}, use `ftp-deploy lock break` if that deploy is gone",
+                existing
+            )
+            .into());
+        }
+        Some(existing) => println!("[ftp-deploy] Replacing stale lock of {}", existing),
+        None => {}
+    }
+
+    ftp_stream.cwd_or_create_recursive(Path::new(&path).parent())?;
+    if creds.state_dir_is_public() && ftp_stream.remote_state(DENY_FILE)?.is_none() {
+        ftp_stream.put(DENY_FILE, &mut Cursor::new(DENY_RULES))?;
+    }
+    ftp_stream.put(
+        REMOTE_LOCK_FILE,
+        &mut Cursor::new(serde_json::to_vec(lock)?),
+    )?;
+
+    // FTP has no exclusive create, so check that no other deploy wrote its lock at the same time
+    if read_remote_with(&mut ftp_stream, &path)?.as_ref() != Some(lock) {
+        return Err("another deploy took the lock at the same time".into());
+    }
+
+    Ok(())
+}
+
+/// Releases the locks, only removing the remote one if it still belongs to this run.
+fn release(base_path: &Path, creds: &FtpCreds, lock: &DeployLock) {
+    let remote = read_remote(creds).and_then(|remote| match remote {
+        Some(remote) if &remote == lock => remove_remote(creds),
+        _ => Ok(()),
+    });
+    if let Err(err) = remote {
+        println!(
+            "[ftp-deploy] Failed to release the lock on the server: {}",
+            err
+        );
+    }
+
+    if let Err(err) = remove_local(base_path) {
+        println!("[ftp-deploy] Failed to release the local lock: {}", err);
+    }
+}
+
+/// Holds the deploy locks until dropped, or until the process is interrupted with Ctrl-C.
+pub struct LockGuard {
+    base_path: PathBuf,
+    creds: FtpCreds,
+    lock: DeployLock,
+}
+
+impl LockGuard {
+    pub fn acquire(
+        base_path: impl AsRef<Path>,
+        creds: &FtpCreds,
+        ttl: u64,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let base_path = base_path.as_ref().to_path_buf();
+        let lock = DeployLock::new(ttl);
+
+        acquire_local(&base_path, &lock)?;
+        if let Err(err) = acquire_remote(creds, &lock) {
+            remove_local(&base_path)?;
+            return Err(err);
+        }
+
+        let (handler_base_path, handler_creds, handler_lock) =
+            (base_path.clone(), creds.clone(), lock.clone());
+        ctrlc::set_handler(move || {
+            println!("[ftp-deploy] Interrupted, releasing the deploy lock.");
+            release(&handler_base_path, &handler_creds, &handler_lock);
+            process::exit(130);
+        })?;
+
+        Ok(Self {
+            base_path,
+            creds: creds.clone(),
+            lock,
+        })
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        release(&self.base_path, &self.creds, &self.lock);
+    }
+}
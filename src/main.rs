@@ -1,11 +1,21 @@
+use std::process::ExitCode;
+
 use clap::{Parser, Subcommand};
 
-use crate::commands::{DeployCommand, FilesCommand, InitCommand, SubcommandDelegate};
+use crate::commands::{
+    AdoptCommand, ApplyCommand, BackupsCommand, CleanCommand, DeployCommand, FilesCommand,
+    HistoryCommand, InitCommand, LockCommand, PullCommand, RollbackCommand, StatusCommand,
+    SubcommandDelegate, VerifyCommand,
+};
 
 mod commands;
 mod config;
 mod ftp;
+mod git;
+mod lock;
+mod prompt;
 mod tracking;
+mod upload;
 
 #[derive(Subcommand)]
 enum Command {
@@ -17,6 +27,36 @@ enum Command {
 
     /// List all tracked files
     Files(FilesCommand),
+
+    /// Show changes that would be deployed, without deploying them
+    Status(StatusCommand),
+
+    /// Check that the remote server matches the tracking information
+    Verify(VerifyCommand),
+
+    /// Track files that are already deployed and identical on the remote server
+    Adopt(AdoptCommand),
+
+    /// Download the remote files into a local directory
+    Pull(PullCommand),
+
+    /// Restore the state of a previous deploy on the remote server
+    Rollback(RollbackCommand),
+
+    /// List or restore backups of overwritten and deleted remote files
+    Backups(BackupsCommand),
+
+    /// Show who deployed what and when
+    History(HistoryCommand),
+
+    /// Show or break the lock that prevents concurrent deploys
+    Lock(LockCommand),
+
+    /// Delete files on the server that are not tracked
+    Clean(CleanCommand),
+
+    /// Apply a plan written by `deploy --plan-out`
+    Apply(ApplyCommand),
 }
 
 #[derive(Parser)]
@@ -27,14 +67,25 @@ struct Args {
     command: Command,
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
     let args = Args::parse();
 
     match args.command {
         Command::Init(init) => init.run(),
         Command::Deploy(deploy) => deploy.run(),
         Command::Files(files) => files.run(),
+        // pending changes are reported through the exit code
+        Command::Status(status) => return status.run(),
+        Command::Verify(verify) => verify.run(),
+        Command::Adopt(adopt) => adopt.run(),
+        Command::Pull(pull) => pull.run(),
+        Command::Rollback(rollback) => rollback.run(),
+        Command::Backups(backups) => backups.run(),
+        Command::History(history) => history.run(),
+        Command::Lock(lock) => lock.run(),
+        Command::Clean(clean) => clean.run(),
+        Command::Apply(apply) => apply.run(),
     }?;
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
@@ -0,0 +1,18 @@
+use std::io::{self, IsTerminal, Write};
+
+/// Asks a yes/no question on the terminal. Answers "no" when stdin is not interactive.
+pub fn confirm(question: &str) -> bool {
+    if !io::stdin().is_terminal() {
+        return false;
+    }
+
+    print!("[ftp-deploy] {} [y/N] ", question);
+    let _ = io::stdout().flush();
+
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
@@ -0,0 +1,293 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader, Cursor, Read},
+    path::{Component, Path, PathBuf},
+    time,
+};
+
+use flate2::read::GzDecoder;
+
+use crate::{
+    tracking::{EntryWalk, FileMode, FileState, FilesTracking, PathMatcher},
+    upload::{ContentsFn, FileSource, HashingReader},
+};
+
+/// Archive formats that can be deployed, detected from the file name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveFormat {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    fn detect(archive: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let name = archive
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Ok(Self::TarGz)
+        } else if name.ends_with(".tar") {
+            Ok(Self::Tar)
+        } else if name.ends_with(".zip") {
+            Ok(Self::Zip)
+        } else {
+            Err(format!(
+                "unsupported archive '{}', expected a .tar, .tar.gz, .tgz or .zip file",
+                archive.display()
+            )
+            .into())
+        }
+    }
+}
+
+/// Kind of an archive entry, only files and directories are deployed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EntryKind {
+    Directory,
+    File,
+    Other,
+}
+
+/// A build artifact deployed instead of the local directory. Nothing is extracted to disk: the
+/// archive is read once to hash the files while it is collected, and again to stream the
+/// contents of the uploaded files.
+pub struct ArchiveFiles {
+    archive: PathBuf,
+    /// Index and size of an entry with the contents by their digest
+    entries: HashMap<String, (usize, u64)>,
+}
+
+impl FileSource for ArchiveFiles {
+    fn open(&self, file: &Path, state: &FileState) -> io::Result<Box<dyn Read + '_>> {
+        let mut contents = None;
+        self.read_each(&[(file, state)], &mut |_, _, reader| {
+            contents = Some(reader.and_then(|reader| {
+                let mut contents = Vec::new();
+                reader.read_to_end(&mut contents)?;
+                Ok(contents)
+            }));
+        });
+
+        Ok(Box::new(Cursor::new(contents.ok_or_else(|| {
+            io::Error::other("the archive was not read")
+        })??)))
+    }
+
+    fn size(&self, _file: &Path, state: &FileState) -> Option<u64> {
+        match state {
+            FileState::File(hash) => self.entries.get(hash).map(|(_, size)| *size),
+            FileState::Directory => None,
+        }
+    }
+
+    fn read_each(&self, files: &[(&Path, &FileState)], f: &mut ContentsFn<'_>) {
+        let mut by_entry: HashMap<usize, Vec<(&Path, &FileState)>> = HashMap::new();
+        for (file, state) in files {
+            match state {
+                FileState::File(hash) if self.entries.contains_key(hash) => {
+                    by_entry
+                        .entry(self.entries[hash].0)
+                        .or_default()
+                        .push((*file, *state));
+                }
+                FileState::File(_) => f(
+                    file,
+                    state,
+                    Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("{} was not read from the archive", file.display()),
+                    )),
+                ),
+                FileState::Directory => f(
+                    file,
+                    state,
+                    Err(io::Error::other("cannot open a directory")),
+                ),
+            }
+        }
+
+        let result = read_entries(&self.archive, |index, _, _, contents| {
+            let Some(files) = by_entry.remove(&index) else {
+                return Ok(());
+            };
+
+            if let [(file, state)] = files[..] {
+                f(file, state, Ok(contents));
+            } else {
+                // files with the same contents share the entry, it can only be read once
+                let mut buffer = Vec::new();
+                contents.read_to_end(&mut buffer)?;
+                for (file, state) in files {
+                    f(file, state, Ok(&mut Cursor::new(&buffer)));
+                }
+            }
+
+            Ok(())
+        });
+
+        let err = match result {
+            Ok(()) => "the entry is no longer in the archive".to_string(),
+            Err(err) => err.to_string(),
+        };
+        for (file, state) in by_entry.into_values().flatten() {
+            f(file, state, Err(io::Error::other(err.clone())));
+        }
+    }
+}
+
+/// Path of an archive entry below `base_path`, `None` if it would point outside of it.
+fn entry_path(base_path: &Path, name: &Path) -> Option<PathBuf> {
+    let mut path = base_path.to_path_buf();
+
+    for component in name.components() {
+        match component {
+            Component::Normal(name) => path.push(name),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    Some(path)
+}
+
+/// Reads the entries of `archive` front to back, calling `f` with the index, name and kind of
+/// every entry and its contents. Whatever `f` does not read of an entry is skipped.
+fn read_entries(
+    archive: &Path,
+    mut f: impl FnMut(usize, &Path, EntryKind, &mut dyn Read) -> io::Result<()>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let format = ArchiveFormat::detect(archive)?;
+    let file = File::open(archive)?;
+
+    match format {
+        ArchiveFormat::Tar => read_tar_entries(BufReader::new(file), f),
+        ArchiveFormat::TarGz => read_tar_entries(GzDecoder::new(BufReader::new(file)), f),
+        ArchiveFormat::Zip => {
+            let mut archive = zip::ZipArchive::new(BufReader::new(file))?;
+
+            for index in 0..archive.len() {
+                let mut entry = archive.by_index(index)?;
+                let name = PathBuf::from(entry.name());
+                let kind = if entry.is_dir() {
+                    EntryKind::Directory
+                } else if entry.is_file() {
+                    EntryKind::File
+                } else {
+                    EntryKind::Other
+                };
+
+                f(index, &name, kind, &mut entry)?;
+            }
+
+            Ok(())
+        }
+    }
+}
+
+fn read_tar_entries(
+    reader: impl Read,
+    mut f: impl FnMut(usize, &Path, EntryKind, &mut dyn Read) -> io::Result<()>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut archive = tar::Archive::new(reader);
+
+    for (index, entry) in archive.entries()?.enumerate() {
+        let mut entry = entry?;
+        let name = entry.path()?.into_owned();
+        let kind = match entry.header().entry_type() {
+            tar::EntryType::Directory => EntryKind::Directory,
+            tar::EntryType::Regular | tar::EntryType::Continuous => EntryKind::File,
+            _ => EntryKind::Other,
+        };
+
+        f(index, &name, kind, &mut entry)?;
+    }
+
+    Ok(())
+}
+
+impl ArchiveFiles {
+    pub fn new(archive: impl AsRef<Path>) -> Self {
+        Self {
+            archive: archive.as_ref().to_path_buf(),
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Reads the archive as if it was extracted into `base_path`, hashes every file and
+    /// compares it against the tracking information like `collect_files` does. The ignore file
+    /// of `base_path` applies to the archive paths.
+    pub fn collect(
+        &mut self,
+        base_path: &Path,
+        files_tracking: FilesTracking,
+        force: bool,
+        only: Option<&PathMatcher>,
+    ) -> Result<HashMap<PathBuf, (FileState, FileMode)>, Box<dyn std::error::Error>> {
+        ArchiveFormat::detect(&self.archive)?;
+
+        println!(
+            "[ftp-deploy] Collecting files from {}",
+            self.archive.display()
+        );
+        let start = time::Instant::now();
+
+        let mut walk = EntryWalk::new(base_path, files_tracking, force, only)?;
+        let entries = &mut self.entries;
+        read_entries(&self.archive, |index, name, kind, contents| {
+            let Some(path) = entry_path(base_path, name) else {
+                println!("[ftp-deploy] Skipping archive entry {}", name.display());
+                return Ok(());
+            };
+
+            match kind {
+                EntryKind::Directory => walk.directory(&path),
+                EntryKind::File => {
+                    let mut reader = HashingReader::new(contents);
+                    let size = io::copy(&mut reader, &mut io::sink())?;
+                    let hash = reader.hash();
+
+                    walk.file(path, FileState::File(hash.clone()));
+                    entries.entry(hash).or_insert((index, size));
+                }
+                EntryKind::Other => println!(
+                    "[ftp-deploy] Skipping archive entry {}, only files and directories are deployed",
+                    name.display()
+                ),
+            }
+
+            Ok(())
+        })?;
+
+        let files = walk.finish();
+
+        println!("[ftp-deploy] Collecting files took {:?}.", start.elapsed());
+
+        Ok(files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> Option<PathBuf> {
+        entry_path(Path::new("."), Path::new(name))
+    }
+
+    #[test]
+    fn places_entries_below_the_base_path() {
+        assert_eq!(path("css/site.css"), Some(PathBuf::from("./css/site.css")));
+        assert_eq!(path("./index.html"), Some(PathBuf::from("./index.html")));
+    }
+
+    #[test]
+    fn rejects_entries_outside_of_the_base_path() {
+        assert_eq!(path("../evil.php"), None);
+        assert_eq!(path("css/../../evil.php"), None);
+        assert_eq!(path("/etc/passwd"), None);
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    config::{BackupMode, FtpCreds},
+    ftp::FtpStreamExt,
+    tracking::{TRACKING_DIR, TrackingFile},
+};
+
+/// Files that were backed up before a deploy overwrote or deleted them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupSet {
+    pub id: String,
+    pub mode: BackupMode,
+    pub files: Vec<PathBuf>,
+}
+
+impl BackupSet {
+    /// Remote directory a backup is moved into in [`BackupMode::Remote`].
+    pub fn remote_dir(creds: &FtpCreds, id: &str) -> String {
+        creds.state_path(format!("backups/{}", id))
+    }
+
+    /// Local directory a backup is downloaded into in [`BackupMode::Local`].
+    pub fn local_dir(base_path: impl AsRef<Path>, id: &str) -> PathBuf {
+        base_path
+            .as_ref()
+            .join(TRACKING_DIR)
+            .join("backups")
+            .join(id)
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct BackupsTracking {
+    pub(crate) backups: Vec<BackupSet>,
+}
+
+impl BackupsTracking {
+    pub fn get(&self, id: &str) -> Option<&BackupSet> {
+        self.backups.iter().find(|set| set.id == id)
+    }
+
+    /// Removes a backup set from the index and deletes its files.
+    pub fn remove(
+        &mut self,
+        id: &str,
+        base_path: impl AsRef<Path>,
+        creds: &FtpCreds,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(index) = self.backups.iter().position(|set| set.id == id) else {
+            return Ok(());
+        };
+
+        let set = self.backups.remove(index);
+        match set.mode {
+            BackupMode::Remote => creds
+                .open_stream()?
+                .remove_recursive(&BackupSet::remote_dir(creds, &set.id))?,
+            BackupMode::Local => fs::remove_dir_all(BackupSet::local_dir(base_path, &set.id))?,
+            BackupMode::None => {}
+        }
+
+        Ok(())
+    }
+
+    /// Records a new backup set and removes all but the newest `keep` ones.
+    pub fn record(
+        &mut self,
+        set: BackupSet,
+        keep: usize,
+        base_path: impl AsRef<Path>,
+        creds: &FtpCreds,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !set.files.is_empty() {
+            println!(
+                "[ftp-deploy] Backed up {} file(s) as {}",
+                set.files.len(),
+                set.id
+            );
+            self.backups.push(set);
+        }
+
+        while self.backups.len() > keep {
+            let id = self.backups[0].id.clone();
+            println!("[ftp-deploy] Removing old backup {}", id);
+            self.remove(&id, base_path.as_ref(), creds)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl TrackingFile for BackupsTracking {
+    const FILE_NAME: &'static str = "backups.json";
+}
@@ -4,13 +4,13 @@ use serde_derive::{Deserialize, Serialize};
 
 use crate::tracking::TrackingFile;
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum FileState {
     File(String),
     Directory,
 }
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct FilesTracking {
     pub(crate) files: HashMap<PathBuf, FileState>,
 }
@@ -0,0 +1,79 @@
+use std::{io::Cursor, path::Path};
+
+use crate::{
+    config::FtpCreds,
+    ftp::FtpStreamExt,
+    git,
+    tracking::{FileState, FilesTracking},
+};
+
+/// File git-ftp keeps the deployed commit in, in the remote base path.
+pub const GIT_FTP_LOG: &str = ".git-ftp.log";
+
+/// The `.git-ftp.log` of sites deployed with git-ftp, which only contains the commit that was
+/// deployed last.
+pub struct GitFtpLog {
+    path: String,
+}
+
+impl GitFtpLog {
+    pub fn new(creds: &FtpCreds) -> Self {
+        Self {
+            path: creds.remote_path(GIT_FTP_LOG),
+        }
+    }
+
+    /// Remote path of the log.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Commit recorded on the server, `None` if there is no log.
+    pub fn read(&self, creds: &FtpCreds) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let mut ftp_stream = creds.open_stream()?;
+
+        if ftp_stream.remote_state(&self.path)?.is_none() {
+            return Ok(None);
+        }
+
+        let contents = ftp_stream.simple_retr(&self.path)?.into_inner();
+        let commit = String::from_utf8_lossy(&contents).trim().to_string();
+
+        Ok((!commit.is_empty()).then_some(commit))
+    }
+
+    pub fn write(&self, creds: &FtpCreds, commit: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let mut ftp_stream = creds.open_stream()?;
+        ftp_stream.cwd_or_create_recursive(Path::new(&self.path).parent())?;
+        ftp_stream.put(GIT_FTP_LOG, &mut Cursor::new(commit.as_bytes()))?;
+
+        Ok(())
+    }
+
+    /// Files tracking as if the tree of `commit` below `base_path` had been deployed, which is
+    /// what git-ftp uploaded.
+    pub fn tracking(
+        base_path: impl AsRef<Path>,
+        commit: &str,
+    ) -> Result<FilesTracking, Box<dyn std::error::Error>> {
+        let base_path = base_path.as_ref();
+        let mut files_tracking = FilesTracking::default();
+
+        for (file, hash) in git::tree_sha256s(base_path, commit)? {
+            let path = base_path.join(file);
+
+            for directory in path.ancestors().skip(1) {
+                files_tracking
+                    .files
+                    .insert(directory.to_path_buf(), FileState::Directory);
+                if directory == base_path {
+                    break;
+                }
+            }
+
+            files_tracking.files.insert(path, FileState::File(hash));
+        }
+
+        Ok(files_tracking)
+    }
+}
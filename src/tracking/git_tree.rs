@@ -0,0 +1,165 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Cursor, Read},
+    path::{Path, PathBuf},
+    time,
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    git,
+    tracking::{
+        EntryWalk, FileMode, FileState, FilesTracking, PathMatcher, TrackingFile, TrackingFileLoder,
+    },
+    upload::{ContentsFn, FileSource},
+};
+
+/// SHA-256 digests of git blobs by their id, so a blob is only read once to compare it.
+#[derive(Default, Serialize, Deserialize)]
+pub struct BlobsTracking {
+    pub(crate) blobs: HashMap<String, String>,
+}
+
+impl TrackingFile for BlobsTracking {
+    const FILE_NAME: &'static str = "blobs.json";
+}
+
+/// The tree of a git commit deployed instead of the working tree. The contents of the files are
+/// read from the object database when they are uploaded.
+pub struct GitTree {
+    base_path: PathBuf,
+    rev: String,
+    /// Blob and size of the files by their SHA-256 digest
+    blobs: HashMap<String, (String, u64)>,
+    /// Digests of the blobs of the tree, only written by `write_blobs`
+    blobs_tracking: BlobsTracking,
+}
+
+impl GitTree {
+    pub fn new(base_path: impl AsRef<Path>, rev: &str) -> Self {
+        Self {
+            base_path: base_path.as_ref().to_path_buf(),
+            rev: rev.to_string(),
+            blobs: HashMap::new(),
+            blobs_tracking: BlobsTracking::default(),
+        }
+    }
+
+    /// Lists the files below the base path in the tree and compares them against the tracking
+    /// information like `collect_files` does. The ignore file of the working tree applies to
+    /// them.
+    pub fn collect(
+        &mut self,
+        files_tracking: FilesTracking,
+        force: bool,
+        only: Option<&PathMatcher>,
+    ) -> Result<HashMap<PathBuf, (FileState, FileMode)>, Box<dyn std::error::Error>> {
+        println!("[ftp-deploy] Collecting files of {} from git", self.rev);
+        let start = time::Instant::now();
+
+        let tree = git::tree_files(&self.base_path, &self.rev)?;
+
+        let mut blobs_tracking = BlobsTracking::load(&self.base_path)?;
+        let unknown = tree
+            .iter()
+            .map(|file| file.blob.as_str())
+            .filter(|blob| !blobs_tracking.blobs.contains_key(*blob))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        if !unknown.is_empty() {
+            blobs_tracking
+                .blobs
+                .extend(git::blob_sha256s(&self.base_path, &unknown)?);
+        }
+
+        // only the blobs of the last deployed tree are kept
+        let current: HashSet<&str> = tree.iter().map(|file| file.blob.as_str()).collect();
+        blobs_tracking
+            .blobs
+            .retain(|blob, _| current.contains(blob.as_str()));
+
+        let mut walk = EntryWalk::new(&self.base_path, files_tracking, force, only)?;
+        for file in tree {
+            let hash = blobs_tracking.blobs[&file.blob].clone();
+
+            walk.file(
+                self.base_path.join(&file.path),
+                FileState::File(hash.clone()),
+            );
+            self.blobs.insert(hash, (file.blob, file.size));
+        }
+        let files = walk.finish();
+        self.blobs_tracking = blobs_tracking;
+
+        println!("[ftp-deploy] Collecting files took {:?}.", start.elapsed());
+
+        Ok(files)
+    }
+
+    /// Stores the digests of the collected blobs, so the next deploy does not read them again.
+    pub fn write_blobs(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.blobs_tracking.write(&self.base_path)
+    }
+}
+
+impl FileSource for GitTree {
+    fn open(&self, file: &Path, state: &FileState) -> io::Result<Box<dyn Read + '_>> {
+        match state {
+            FileState::File(hash) => match self.blobs.get(hash) {
+                Some((blob, _)) => Ok(Box::new(Cursor::new(git::read_blob(
+                    &self.base_path,
+                    blob,
+                )?))),
+                None => Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} is not part of {}", file.display(), self.rev),
+                )),
+            },
+            FileState::Directory => Err(io::Error::other("cannot open a directory")),
+        }
+    }
+
+    fn size(&self, _file: &Path, state: &FileState) -> Option<u64> {
+        match state {
+            FileState::File(hash) => self.blobs.get(hash).map(|(_, size)| *size),
+            FileState::Directory => None,
+        }
+    }
+
+    fn read_each(&self, files: &[(&Path, &FileState)], f: &mut ContentsFn<'_>) {
+        // a blob shared by several files is requested once for every one of them
+        let mut wanted = Vec::new();
+        for (file, state) in files {
+            match state {
+                FileState::File(hash) if self.blobs.contains_key(hash) => {
+                    wanted.push((self.blobs[hash].0.as_str(), *file, *state));
+                }
+                _ => f(
+                    file,
+                    state,
+                    Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("{} is not part of {}", file.display(), self.rev),
+                    )),
+                ),
+            }
+        }
+
+        let blobs = wanted.iter().map(|(blob, _, _)| *blob).collect::<Vec<_>>();
+        let mut read = 0;
+        let result = git::cat_blobs(&self.base_path, &blobs, |_, contents| {
+            let (_, file, state) = wanted[read];
+            read += 1;
+            f(file, state, Ok(contents));
+            Ok(())
+        });
+
+        if let Err(err) = result {
+            for (_, file, state) in &wanted[read..] {
+                f(file, state, Err(io::Error::other(err.to_string())));
+            }
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    config::FtpCreds, ftp::FtpStreamExt, git, tracking::TRACKING_DIR, upload::UploadReport,
+};
+
+const HISTORY_FILE: &str = "history.jsonl";
+
+/// How far a recorded run got.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    /// Every change was made
+    #[default]
+    Complete,
+    /// Some files failed, the others were changed
+    Partial,
+    /// The run was aborted by an error after making the listed changes
+    Failed,
+}
+
+impl RunStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunStatus::Complete => "complete",
+            RunStatus::Partial => "partial",
+            RunStatus::Failed => "failed",
+        }
+    }
+}
+
+/// Record of a single deploy or rollback.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: String,
+    /// Command that changed the server, `deploy` or `rollback`
+    pub command: String,
+    /// RFC 3339 UTC time the run started at
+    pub timestamp: String,
+    pub user: String,
+    pub host: String,
+    /// Server and remote base path deployed to
+    pub target: String,
+    /// Git commit checked out while deploying, or deployed with `--git-rev`
+    pub commit: Option<String>,
+    /// Whether the working tree had uncommitted changes
+    #[serde(default)]
+    pub dirty: bool,
+    pub created: Vec<PathBuf>,
+    pub updated: Vec<PathBuf>,
+    pub deleted: Vec<PathBuf>,
+    #[serde(default)]
+    pub moved: Vec<PathBuf>,
+    pub failed: Vec<PathBuf>,
+    /// Bytes uploaded
+    pub bytes: u64,
+    pub duration_ms: u64,
+    #[serde(default)]
+    pub status: RunStatus,
+    /// Error the run was aborted with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl HistoryEntry {
+    pub fn new(
+        id: &str,
+        command: &str,
+        started: DateTime<Utc>,
+        base_path: impl AsRef<Path>,
+        creds: &FtpCreds,
+        report: UploadReport,
+    ) -> Self {
+        Self {
+            id: id.to_string(),
+            command: command.to_string(),
+            timestamp: started.to_rfc3339_opts(SecondsFormat::Secs, true),
+            user: Self::current_user(),
+            host: Self::current_host(),
+            target: format!("{}{}", creds.server, creds.remote_path(".")),
+            commit: git::head_commit(&base_path),
+            dirty: git::is_dirty(&base_path, &[TRACKING_DIR]).unwrap_or(false),
+            status: if report.failed.is_empty() {
+                RunStatus::Complete
+            } else {
+                RunStatus::Partial
+            },
+            error: None,
+            created: report.created,
+            updated: report.updated,
+            deleted: report.deleted,
+            moved: report.moved,
+            failed: report.failed,
+            bytes: report.bytes,
+            duration_ms: (Utc::now() - started).num_milliseconds().max(0) as u64,
+        }
+    }
+
+    /// Marks the run as aborted by an error.
+    pub fn aborted(mut self, err: &dyn std::error::Error) -> Self {
+        self.status = RunStatus::Failed;
+        self.error = Some(err.to_string());
+        self
+    }
+
+    /// Name of the user running ftp-deploy.
+    pub fn current_user() -> String {
+        std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .unwrap_or_else(|_| "unknown".to_string())
+    }
+
+    /// Name of the machine running ftp-deploy.
+    pub fn current_host() -> String {
+        gethostname::gethostname().to_string_lossy().into_owned()
+    }
+
+    pub fn touches(&self, path: &Path) -> bool {
+        [
+            &self.created,
+            &self.updated,
+            &self.deleted,
+            &self.moved,
+            &self.failed,
+        ]
+        .iter()
+        .any(|files| files.iter().any(|file| file == path))
+    }
+}
+
+/// Append-only log of all deploys in `.ftp/history.jsonl`, one JSON entry per line.
+pub struct History {
+    path: PathBuf,
+}
+
+impl History {
+    /// Appends an entry for a finished run, uploading the history afterwards if configured.
+    pub fn record(
+        &self,
+        entry: &HistoryEntry,
+        creds: &FtpCreds,
+        upload: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.append(entry)?;
+
+        if upload && let Err(err) = self.upload(creds) {
+            println!("[ftp-deploy] Failed to upload the deploy history: {}", err);
+        }
+
+        Ok(())
+    }
+
+    pub fn new(base_path: impl AsRef<Path>) -> Self {
+        Self {
+            path: base_path.as_ref().join(TRACKING_DIR).join(HISTORY_FILE),
+        }
+    }
+
+    pub fn append(&self, entry: &HistoryEntry) -> Result<(), Box<dyn std::error::Error>> {
+        fs::create_dir_all(self.path.parent().unwrap())?;
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+
+        Ok(())
+    }
+
+    /// Uploads a copy of the history to `.ftp-deploy/history.jsonl` on the server.
+    pub fn upload(&self, creds: &FtpCreds) -> Result<(), Box<dyn std::error::Error>> {
+        let remote_path = creds.state_path(HISTORY_FILE);
+
+        let mut ftp_stream = creds.open_stream()?;
+        ftp_stream.cwd_or_create_recursive(Path::new(&remote_path).parent())?;
+        ftp_stream.put(HISTORY_FILE, &mut File::open(&self.path)?)?;
+
+        Ok(())
+    }
+
+    /// All recorded entries, oldest first.
+    pub fn entries(&self) -> Result<Vec<HistoryEntry>, Box<dyn std::error::Error>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut entries = Vec::new();
+        for line in BufReader::new(File::open(&self.path)?).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                entries.push(serde_json::from_str(&line)?);
+            }
+        }
+
+        Ok(entries)
+    }
+}
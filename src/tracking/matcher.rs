@@ -0,0 +1,144 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+/// Matches tracked paths against `.gitignore` style patterns, relative to the deployed
+/// directory.
+#[derive(Clone)]
+pub struct PathMatcher {
+    root: PathBuf,
+    gitignore: Gitignore,
+}
+
+impl PathMatcher {
+    pub fn new(
+        base_path: impl AsRef<Path>,
+        patterns: &[String],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut builder = GitignoreBuilder::new("");
+        for pattern in patterns {
+            builder.add_line(None, pattern)?;
+        }
+
+        Ok(Self {
+            root: base_path.as_ref().to_path_buf(),
+            gitignore: builder.build()?,
+        })
+    }
+
+    /// Pattern matching exactly the file or directory `path`, relative to the base path. Glob
+    /// syntax in the name is escaped.
+    pub fn literal_pattern(path: &str) -> String {
+        let path = path.trim_start_matches("./").trim_start_matches('/');
+        let escaped: String = path
+            .chars()
+            .flat_map(|c| match c {
+                '\\' | '*' | '?' | '[' | ']' | '{' | '}' => vec!['\\', c],
+                _ => vec![c],
+            })
+            .collect();
+
+        // trailing spaces are dropped from patterns unless escaped
+        let trimmed = escaped.trim_end_matches(' ');
+        format!(
+            "/{}{}",
+            trimmed,
+            "\\ ".repeat(escaped.len() - trimmed.len())
+        )
+    }
+
+    /// Glob matching relative to the base path rather than at any depth, e.g. `*.css` only
+    /// matches stylesheets in the base path itself.
+    pub fn anchored_pattern(glob: &str) -> String {
+        format!("/{}", glob.trim_start_matches("./").trim_start_matches('/'))
+    }
+
+    /// Reads the patterns from an ignore file, matching nothing if it does not exist.
+    pub fn from_file(
+        base_path: impl AsRef<Path>,
+        file: impl AsRef<Path>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let patterns = match fs::read_to_string(file) {
+            Ok(contents) => contents.lines().map(str::to_string).collect(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        Self::new(base_path, &patterns)
+    }
+
+    /// Returns the pattern matching `path` or one of its parent directories, if any.
+    pub fn matched(&self, path: &Path, is_dir: bool) -> Option<&str> {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        if relative.has_root() {
+            return None;
+        }
+
+        self.gitignore
+            .matched_path_or_any_parents(relative, is_dir)
+            .inner()
+            .filter(|glob| !glob.is_whitelist())
+            .map(|glob| glob.original())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(patterns: &[String]) -> PathMatcher {
+        PathMatcher::new(".", patterns).unwrap()
+    }
+
+    fn matches(matcher: &PathMatcher, path: &str) -> bool {
+        matcher.matched(Path::new(path), false).is_some()
+    }
+
+    #[test]
+    fn literal_pattern_escapes_glob_syntax() {
+        assert_eq!(
+            PathMatcher::literal_pattern("./a/[b]*.txt"),
+            "/a/\\[b\\]\\*.txt"
+        );
+
+        let only = matcher(&[PathMatcher::literal_pattern("./img/[1].png")]);
+        assert!(matches(&only, "./img/[1].png"));
+        assert!(!matches(&only, "./img/1.png"));
+    }
+
+    #[test]
+    fn literal_pattern_keeps_trailing_spaces() {
+        let only = matcher(&[PathMatcher::literal_pattern("notes ")]);
+
+        assert!(matches(&only, "./notes "));
+        assert!(!matches(&only, "./notes"));
+    }
+
+    #[test]
+    fn literal_pattern_is_anchored() {
+        let only = matcher(&[PathMatcher::literal_pattern("index.html")]);
+
+        assert!(matches(&only, "./index.html"));
+        assert!(!matches(&only, "./blog/index.html"));
+    }
+
+    #[test]
+    fn anchored_pattern_matches_from_the_base_path() {
+        let only = matcher(&[PathMatcher::anchored_pattern("./*.css")]);
+
+        assert!(matches(&only, "./site.css"));
+        assert!(!matches(&only, "./theme/site.css"));
+    }
+
+    #[test]
+    fn directory_pattern_matches_its_contents() {
+        let only = matcher(&[PathMatcher::anchored_pattern("assets/")]);
+
+        assert!(only.matched(Path::new("./assets"), true).is_some());
+        assert!(matches(&only, "./assets/img/logo.png"));
+        assert!(!matches(&only, "./other/assets"));
+    }
+}
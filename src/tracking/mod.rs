@@ -1,10 +1,50 @@
 use std::{fs, io::Write, path::Path};
 
+use chrono::Utc;
 use serde::{Serialize, de::DeserializeOwned};
 
 mod files;
 pub use files::*;
 
+mod remote;
+pub use remote::*;
+
+mod releases;
+pub use releases::*;
+
+mod snapshots;
+pub use snapshots::*;
+
+mod backups;
+pub use backups::*;
+
+mod history;
+pub use history::*;
+
+mod shared;
+pub use shared::*;
+
+mod git_ftp;
+pub use git_ftp::*;
+
+mod matcher;
+pub use matcher::*;
+
+mod walk;
+pub use walk::*;
+
+mod archive;
+pub use archive::*;
+
+mod git_tree;
+pub use git_tree::*;
+
+mod plan;
+pub use plan::*;
+
+mod update;
+pub use update::*;
+
 pub const IGNORE_FILE_NAME: &str = ".ftpignore";
 
 pub fn create_ignore_file(base_path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
@@ -24,7 +64,16 @@ pub fn create_ignore_file(base_path: impl AsRef<Path>) -> Result<(), Box<dyn std
     Ok(())
 }
 
-const TRACKING_DIR: &str = ".ftp";
+pub const TRACKING_DIR: &str = ".ftp";
+
+/// Default directory below the remote base path that holds data of ftp-deploy itself, see
+/// `FtpCreds::state_dir`.
+pub const REMOTE_DIR: &str = ".ftp-deploy";
+
+/// Creates a new, chronologically sortable id for a deploy.
+pub fn new_deploy_id() -> String {
+    Utc::now().format("%Y%m%d%H%M%S%3f").to_string()
+}
 
 pub fn create_tracking_dir(base_path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
     let tracking_dir = base_path.as_ref().join(TRACKING_DIR);
@@ -33,6 +82,11 @@ pub fn create_tracking_dir(base_path: impl AsRef<Path>) -> Result<(), Box<dyn st
 }
 
 pub trait TrackingFileLoder {
+    /// Loads the tracking file, falling back to the default without creating it.
+    fn load(base_path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>>
+    where
+        Self: Sized;
+
     fn load_or_create(base_path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>>
     where
         Self: Sized;
@@ -45,6 +99,20 @@ pub trait TrackingFile: Default + Serialize + DeserializeOwned {
 }
 
 impl<T: TrackingFile> TrackingFileLoder for T {
+    fn load(base_path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>>
+    where
+        Self: Sized,
+    {
+        let file_path = base_path.as_ref().join(".ftp/").join(Self::FILE_NAME);
+
+        if !file_path.exists() {
+            return Ok(Self::default());
+        }
+
+        let file = fs::File::open(file_path)?;
+        Ok(serde_json::from_reader(file)?)
+    }
+
     fn load_or_create(base_path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>>
     where
         Self: Sized,
@@ -0,0 +1,136 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::tracking::{FileUpdate, FilesTracking, RemoteTracking};
+
+/// Where the files of a plan are read from when it is applied.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanSource {
+    /// The deployed directory
+    #[default]
+    Directory,
+    /// A .tar, .tar.gz or .zip archive
+    Archive(PathBuf),
+    /// The tree of a git commit
+    GitCommit(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlannedUpdate {
+    #[serde(flatten)]
+    pub update: FileUpdate,
+    /// Bytes uploaded for a created or updated file
+    pub size: Option<u64>,
+}
+
+/// Changes computed by `deploy --plan-out`, to be reviewed and executed later by `apply`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Plan {
+    /// RFC 3339 UTC time the plan was made at
+    pub created: String,
+    /// Server and remote base path the plan deploys to
+    pub target: String,
+    /// Digest of the files and remote tracking the plan was computed against
+    pub tracking: String,
+    pub source: PlanSource,
+    /// Whether only selected paths were planned
+    pub partial: bool,
+    /// Upload every file under a temporary name and rename it into place once complete
+    pub temp_upload: bool,
+    /// Files that are ignored now, kept on the server and no longer tracked. Unless the
+    /// `ignored` policy deletes them, they are listed here instead of asking about them.
+    #[serde(default)]
+    pub ignored: Vec<PathBuf>,
+    pub updates: Vec<PlannedUpdate>,
+}
+
+impl Plan {
+    /// Digest of the tracking information, independent of the order of its entries.
+    pub fn tracking_digest(
+        files_tracking: &FilesTracking,
+        remote_tracking: &RemoteTracking,
+    ) -> String {
+        let files: BTreeMap<_, _> = files_tracking.files.iter().collect();
+        let remote: BTreeMap<_, _> = remote_tracking.files.iter().collect();
+        let contents = serde_json::to_vec(&(files, remote)).unwrap_or_default();
+
+        format!("{:x}", Sha256::digest(contents))
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = File::open(path.as_ref())
+            .map_err(|err| format!("failed to open plan {}: {}", path.as_ref().display(), err))?;
+
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracking::{FileState, RemoteFileState};
+
+    fn tracking(
+        files: &[(&str, &str)],
+        remote: &[(&str, usize)],
+    ) -> (FilesTracking, RemoteTracking) {
+        let mut files_tracking = FilesTracking::default();
+        for (path, hash) in files {
+            files_tracking
+                .files
+                .insert(PathBuf::from(path), FileState::File(hash.repeat(64)));
+        }
+
+        let mut remote_tracking = RemoteTracking::default();
+        for (path, size) in remote {
+            remote_tracking.files.insert(
+                PathBuf::from(path),
+                RemoteFileState {
+                    size: Some(*size),
+                    modified: None,
+                },
+            );
+        }
+
+        (files_tracking, remote_tracking)
+    }
+
+    fn digest(files: &[(&str, &str)], remote: &[(&str, usize)]) -> String {
+        let (files_tracking, remote_tracking) = tracking(files, remote);
+        Plan::tracking_digest(&files_tracking, &remote_tracking)
+    }
+
+    #[test]
+    fn digests_tracking_independent_of_order() {
+        assert_eq!(
+            digest(&[("./a", "a"), ("./b", "b")], &[("./a", 1), ("./b", 2)]),
+            digest(&[("./b", "b"), ("./a", "a")], &[("./b", 2), ("./a", 1)])
+        );
+    }
+
+    #[test]
+    fn detects_changed_tracking() {
+        let planned = digest(&[("./a", "a")], &[("./a", 1)]);
+
+        assert_ne!(planned, digest(&[("./a", "b")], &[("./a", 1)]));
+        assert_ne!(
+            planned,
+            digest(&[("./a", "a"), ("./b", "b")], &[("./a", 1)])
+        );
+        assert_ne!(planned, digest(&[("./a", "a")], &[("./a", 2)]));
+        assert_ne!(planned, digest(&[("./a", "a")], &[]));
+    }
+}
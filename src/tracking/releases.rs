@@ -0,0 +1,29 @@
+use std::path::PathBuf;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{config::FtpCreds, tracking::TrackingFile};
+
+/// Release that is currently switched to when deploying atomically.
+#[derive(Default, Serialize, Deserialize)]
+pub struct ReleasesTracking {
+    pub(crate) current: Option<String>,
+}
+
+impl TrackingFile for ReleasesTracking {
+    const FILE_NAME: &'static str = "releases.json";
+}
+
+impl ReleasesTracking {
+    /// Credentials resolving tracked paths in the live tree, which is `current` once deploys are
+    /// atomic. The state directory is still resolved with `creds`.
+    pub fn live_creds(&self, creds: &FtpCreds) -> FtpCreds {
+        match &self.current {
+            Some(_) => FtpCreds {
+                base_path: PathBuf::from(creds.remote_path("current")),
+                ..creds.clone()
+            },
+            None => creds.clone(),
+        }
+    }
+}
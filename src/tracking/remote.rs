@@ -0,0 +1,38 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::tracking::TrackingFile;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteFileState {
+    pub size: Option<usize>,
+    pub modified: Option<i64>,
+}
+
+impl RemoteFileState {
+    /// Compares two states, ignoring attributes the server did not report.
+    pub fn matches(&self, other: &RemoteFileState) -> bool {
+        let size_matches = match (self.size, other.size) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        };
+
+        let modified_matches = match (self.modified, other.modified) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        };
+
+        size_matches && modified_matches
+    }
+}
+
+/// Remote size and modification time of every file as recorded right after it was uploaded.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct RemoteTracking {
+    pub(crate) files: HashMap<PathBuf, RemoteFileState>,
+}
+
+impl TrackingFile for RemoteTracking {
+    const FILE_NAME: &'static str = "remote.json";
+}
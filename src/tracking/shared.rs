@@ -0,0 +1,101 @@
+use std::{io::Cursor, path::Path};
+
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::FtpCreds,
+    ftp::FtpStreamExt,
+    tracking::{FilesTracking, RemoteTracking},
+};
+
+const MANIFEST_FILE: &str = "tracking.json";
+
+#[derive(Deserialize)]
+struct Manifest {
+    files: FilesTracking,
+    #[serde(default)]
+    remote: RemoteTracking,
+}
+
+#[derive(Serialize)]
+struct ManifestRef<'a> {
+    files: &'a FilesTracking,
+    remote: &'a RemoteTracking,
+}
+
+/// The files and remote tracking kept in `.ftp-deploy/tracking.json` on the server, so
+/// everyone deploying the site compares against the same state.
+pub struct SharedTracking {
+    path: String,
+    /// Digest of the manifest as downloaded, `None` if there was none
+    version: Option<String>,
+}
+
+fn version(contents: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(contents))
+}
+
+impl SharedTracking {
+    fn fetch(&self, creds: &FtpCreds) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+        let mut ftp_stream = creds.open_stream()?;
+
+        if ftp_stream.remote_state(&self.path)?.is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(ftp_stream.simple_retr(&self.path)?.into_inner()))
+    }
+
+    /// Replaces the local tracking with the manifest on the server, if there is one.
+    pub fn load_into(
+        creds: &FtpCreds,
+        files_tracking: &mut FilesTracking,
+        remote_tracking: &mut RemoteTracking,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut shared = Self {
+            path: creds.state_path(MANIFEST_FILE),
+            version: None,
+        };
+
+        if let Some(contents) = shared.fetch(creds)? {
+            let manifest: Manifest = serde_json::from_slice(&contents)?;
+            *files_tracking = manifest.files;
+            *remote_tracking = manifest.remote;
+            shared.version = Some(version(&contents));
+        } else {
+            println!("[ftp-deploy] No shared tracking on the server yet, using the local one.");
+        }
+
+        Ok(shared)
+    }
+
+    /// Uploads the tracking, unless another deploy changed the manifest since it was loaded.
+    pub fn store(
+        &mut self,
+        creds: &FtpCreds,
+        files_tracking: &FilesTracking,
+        remote_tracking: &RemoteTracking,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let current = self.fetch(creds)?.map(|contents| version(&contents));
+        if current != self.version {
+            return Err(
+                "the shared tracking on the server was changed by another deploy, \
+                 the local .ftp/ tracking has the state of this deploy"
+                    .into(),
+            );
+        }
+
+        let contents = serde_json::to_vec(&ManifestRef {
+            files: files_tracking,
+            remote: remote_tracking,
+        })?;
+
+        let mut ftp_stream = creds.open_stream()?;
+        ftp_stream.cwd_or_create_recursive(Path::new(&self.path).parent())?;
+        ftp_stream.put(MANIFEST_FILE, &mut Cursor::new(&contents))?;
+
+        self.version = Some(version(&contents));
+        Ok(())
+    }
+}
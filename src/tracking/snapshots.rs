@@ -0,0 +1,209 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    tracking::{FileState, FilesTracking, TRACKING_DIR},
+    upload::{FileSource, HashingReader},
+};
+
+const SNAPSHOTS_DIR: &str = "deploys";
+const OBJECTS_DIR: &str = "objects";
+
+/// Tracking information and file contents of past deploys, stored in `.ftp/deploys/<id>.json`
+/// and `.ftp/objects/<sha256>`, so a deploy can be rolled back.
+pub struct Snapshots {
+    dir: PathBuf,
+}
+
+impl Snapshots {
+    pub fn new(base_path: impl AsRef<Path>) -> Self {
+        Self {
+            dir: base_path.as_ref().join(TRACKING_DIR),
+        }
+    }
+
+    fn snapshot_path(&self, id: &str) -> PathBuf {
+        self.dir.join(SNAPSHOTS_DIR).join(format!("{}.json", id))
+    }
+
+    fn object_path(&self, hash: &str) -> PathBuf {
+        self.dir.join(OBJECTS_DIR).join(hash)
+    }
+
+    /// Stores the contents read from `reader` as the object `hash`. Contents that do not hash
+    /// to it, e.g. because the file changed since it was collected, are not stored.
+    fn store_object(&self, hash: &str, reader: impl Read) -> io::Result<bool> {
+        let object_path = self.object_path(hash);
+        let temp_path = object_path.with_extension("tmp");
+
+        let mut reader = HashingReader::new(reader);
+        io::copy(&mut reader, &mut File::create(&temp_path)?)?;
+
+        if reader.state() != FileState::File(hash.to_string()) {
+            fs::remove_file(&temp_path)?;
+            return Ok(false);
+        }
+
+        fs::rename(&temp_path, &object_path)?;
+        Ok(true)
+    }
+
+    /// Whether the object `hash` is stored and its contents still hash to it.
+    fn object_intact(&self, hash: &str) -> bool {
+        let Ok(mut file) = File::open(self.object_path(hash)) else {
+            return false;
+        };
+
+        let mut hasher = Sha256::new();
+        io::copy(&mut file, &mut hasher).is_ok() && format!("{:x}", hasher.finalize()) == hash
+    }
+
+    /// Ids of all stored snapshots, oldest first.
+    pub fn ids(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let dir = self.dir.join(SNAPSHOTS_DIR);
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if let Some(id) = path.file_stem().and_then(|id| id.to_str()) {
+                ids.push(id.to_string());
+            }
+        }
+
+        ids.sort();
+        Ok(ids)
+    }
+
+    pub fn load(&self, id: &str) -> Result<FilesTracking, Box<dyn std::error::Error>> {
+        let file = File::open(self.snapshot_path(id))?;
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    /// Stores the tracking information and a copy of every tracked file not stored yet. The
+    /// copies are read from `source`, the files the deploy uploaded.
+    pub fn save(
+        &self,
+        id: &str,
+        files_tracking: &FilesTracking,
+        source: &dyn FileSource,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        fs::create_dir_all(self.dir.join(SNAPSHOTS_DIR))?;
+        fs::create_dir_all(self.dir.join(OBJECTS_DIR))?;
+
+        // one copy per digest, files with the same contents share it
+        let missing: HashMap<&str, (&Path, &FileState)> = files_tracking
+            .files
+            .iter()
+            .filter_map(|(path, state)| match state {
+                FileState::File(hash) if !self.object_path(hash).exists() => {
+                    Some((hash.as_str(), (path.as_path(), state)))
+                }
+                _ => None,
+            })
+            .collect();
+
+        source.read_each(
+            &missing.into_values().collect::<Vec<_>>(),
+            &mut |path, state, reader| {
+                let FileState::File(hash) = state else {
+                    return;
+                };
+
+                match reader.and_then(|reader| self.store_object(hash, reader)) {
+                    Ok(true) => {}
+                    Ok(false) => println!(
+                        "[ftp-deploy] Not storing a copy of '{}', it changed since it was deployed",
+                        path.display()
+                    ),
+                    Err(err) => println!(
+                        "[ftp-deploy] Failed to store a copy of '{}': {}",
+                        path.display(),
+                        err
+                    ),
+                }
+            },
+        );
+
+        let file = File::create(self.snapshot_path(id))?;
+        serde_json::to_writer(file, files_tracking)?;
+
+        Ok(())
+    }
+
+    /// Returns the tracked files of a snapshot whose contents are not stored, or whose stored
+    /// copy no longer hashes to the tracked digest.
+    pub fn missing_objects<'a>(&self, files_tracking: &'a FilesTracking) -> Vec<&'a PathBuf> {
+        files_tracking
+            .files
+            .iter()
+            .filter_map(|(path, state)| match state {
+                FileState::File(hash) if !self.object_intact(hash) => Some(path),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Removes all but the newest `keep` snapshots and the file copies no longer referenced.
+    pub fn prune(&self, keep: usize) -> Result<(), Box<dyn std::error::Error>> {
+        let ids = self.ids()?;
+        let (old, kept) = ids.split_at(ids.len().saturating_sub(keep));
+
+        for id in old {
+            fs::remove_file(self.snapshot_path(id))?;
+        }
+
+        let mut referenced = HashSet::new();
+        for id in kept {
+            referenced.extend(
+                self.load(id)?
+                    .files
+                    .into_values()
+                    .filter_map(|state| match state {
+                        FileState::File(hash) => Some(hash),
+                        FileState::Directory => None,
+                    }),
+            );
+        }
+
+        let objects = self.dir.join(OBJECTS_DIR);
+        if !objects.exists() {
+            return Ok(());
+        }
+
+        for entry in fs::read_dir(objects)? {
+            let entry = entry?;
+            if !referenced.contains(entry.file_name().to_string_lossy().as_ref()) {
+                fs::remove_file(entry.path())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FileSource for Snapshots {
+    fn open(&self, _file: &Path, state: &FileState) -> io::Result<Box<dyn Read + '_>> {
+        match state {
+            FileState::File(hash) => Ok(Box::new(File::open(self.object_path(hash))?)),
+            FileState::Directory => Err(io::Error::other("cannot open a directory")),
+        }
+    }
+
+    fn size(&self, _file: &Path, state: &FileState) -> Option<u64> {
+        match state {
+            FileState::File(hash) => fs::metadata(self.object_path(hash))
+                .map(|metadata| metadata.len())
+                .ok(),
+            FileState::Directory => None,
+        }
+    }
+}
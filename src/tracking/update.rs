@@ -0,0 +1,490 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use itertools::Itertools;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::tracking::{FileMode, FileState, FilesTracking, PathMatcher};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum FileUpdateType {
+    CreateOrUpdate,
+    /// Rename on the server instead of uploading the same content again
+    Move,
+    Delete,
+}
+
+impl FileUpdateType {
+    pub fn get_verb(&self) -> &str {
+        match self {
+            FileUpdateType::CreateOrUpdate => "create or update",
+            FileUpdateType::Move => "move",
+            FileUpdateType::Delete => "delete",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum FileType {
+    File,
+    Directory,
+}
+
+impl From<&FileState> for FileType {
+    fn from(value: &FileState) -> Self {
+        match value {
+            FileState::File(_) => Self::File,
+            FileState::Directory => Self::Directory,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileUpdate {
+    pub update_type: FileUpdateType,
+    pub file_type: FileType,
+    pub file: PathBuf,
+    pub state: FileState,
+    /// Previous path of a moved file or directory
+    pub from: Option<PathBuf>,
+}
+
+impl PartialOrd for FileUpdate {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FileUpdate {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        match (self.update_type, other.update_type) {
+            (FileUpdateType::Delete, FileUpdateType::Delete) => {
+                // sort so that first items with file_type File and then Directory and then by file name
+                match (self.file_type, other.file_type) {
+                    (FileType::File, FileType::Directory) => std::cmp::Ordering::Less,
+                    (FileType::Directory, FileType::File) => std::cmp::Ordering::Greater,
+                    _ => self.file.cmp(&other.file),
+                }
+            }
+            (FileUpdateType::CreateOrUpdate, FileUpdateType::CreateOrUpdate) => {
+                // sort so that first items with file_type Directory and then File and then by file name
+                match (self.file_type, other.file_type) {
+                    (FileType::Directory, FileType::File) => std::cmp::Ordering::Less,
+                    (FileType::File, FileType::Directory) => std::cmp::Ordering::Greater,
+                    _ => self.file.cmp(&other.file),
+                }
+            }
+            (FileUpdateType::Move, FileUpdateType::Move) => self.file.cmp(&other.file),
+            (a, b) => a.cmp(&b),
+        }
+    }
+}
+
+impl FileUpdate {
+    /// Updates needed to apply the changed files. Paths matching a `protect` pattern are never
+    /// touched on the server and are skipped with a notice.
+    pub fn from_files(
+        files: &HashMap<PathBuf, (FileState, FileMode)>,
+        protect: &PathMatcher,
+    ) -> Vec<FileUpdate> {
+        files
+            .iter()
+            .sorted_by_key(|(path, _)| *path)
+            .filter_map(|(path, (state, mode))| {
+                let update_mode = match mode {
+                    FileMode::Created | FileMode::Updated => FileUpdateType::CreateOrUpdate,
+                    FileMode::Deleted => FileUpdateType::Delete,
+                    _ => return None,
+                };
+
+                if let Some(pattern) =
+                    protect.matched(path, *state == FileState::Directory)
+                {
+                    println!(
+                        "[ftp-deploy] Skipping protected path '{}' ({} skipped, it matches the protect pattern '{}')",
+                        path.display(),
+                        update_mode.get_verb(),
+                        pattern
+                    );
+                    return None;
+                }
+
+                Some(FileUpdate {
+                    file: path.clone(),
+                    file_type: state.into(),
+                    update_type: update_mode,
+                    state: state.clone(),
+                    from: None,
+                })
+            })
+            .collect()
+    }
+
+    /// Deleted and created entries under `directory`, keyed by their path relative to it.
+    fn contents(
+        updates: &[FileUpdate],
+        directory: &Path,
+        update_type: FileUpdateType,
+    ) -> Vec<(PathBuf, FileState)> {
+        updates
+            .iter()
+            .filter(|update| update.update_type == update_type && update.file != directory)
+            .filter_map(|update| {
+                let relative = update.file.strip_prefix(directory).ok()?;
+                Some((relative.to_path_buf(), update.state.clone()))
+            })
+            .sorted_by(|(a, _), (b, _)| a.cmp(b))
+            .collect()
+    }
+
+    /// Replaces deletions and creations of the same content with moves. A directory whose
+    /// entire contents were moved along with it becomes a single move, other files are paired
+    /// up by their hash.
+    pub fn detect_moves(
+        mut updates: Vec<FileUpdate>,
+        files: &HashMap<PathBuf, (FileState, FileMode)>,
+    ) -> Vec<FileUpdate> {
+        let mut moves = Vec::new();
+
+        let deleted_dirs = updates
+            .iter()
+            .filter(|update| {
+                update.update_type == FileUpdateType::Delete
+                    && update.file_type == FileType::Directory
+            })
+            .map(|update| update.file.clone())
+            .sorted_by_key(|path| path.components().count())
+            .collect_vec();
+
+        for from in deleted_dirs {
+            // already part of the move of a parent directory
+            if !updates.iter().any(|update| update.file == from) {
+                continue;
+            }
+
+            let deleted = Self::contents(&updates, &from, FileUpdateType::Delete);
+            if deleted.is_empty() {
+                continue;
+            }
+
+            let target = updates
+                .iter()
+                .filter(|update| {
+                    update.update_type == FileUpdateType::CreateOrUpdate
+                        && update.file_type == FileType::Directory
+                        && files.get(&update.file).map(|(_, mode)| *mode) == Some(FileMode::Created)
+                })
+                .map(|update| update.file.clone())
+                .find(|to| Self::contents(&updates, to, FileUpdateType::CreateOrUpdate) == deleted);
+
+            // a directory cannot be moved into itself
+            if let Some(to) = target.filter(|to| !to.starts_with(&from)) {
+                updates.retain(|update| {
+                    !update.file.starts_with(&from) && !update.file.starts_with(&to)
+                });
+                moves.push(FileUpdate {
+                    update_type: FileUpdateType::Move,
+                    file_type: FileType::Directory,
+                    file: to,
+                    state: FileState::Directory,
+                    from: Some(from),
+                });
+            }
+        }
+
+        let mut deleted_files: HashMap<FileState, Vec<PathBuf>> = HashMap::new();
+        for update in &updates {
+            if update.update_type == FileUpdateType::Delete && update.file_type == FileType::File {
+                deleted_files
+                    .entry(update.state.clone())
+                    .or_default()
+                    .push(update.file.clone());
+            }
+        }
+
+        let mut moved_from = HashSet::new();
+        for update in updates.iter_mut() {
+            let created = files.get(&update.file).map(|(_, mode)| *mode) == Some(FileMode::Created);
+            if update.update_type != FileUpdateType::CreateOrUpdate
+                || update.file_type != FileType::File
+                || !created
+            {
+                continue;
+            }
+
+            if let Some(from) = deleted_files
+                .get_mut(&update.state)
+                .and_then(|paths| paths.pop())
+            {
+                update.update_type = FileUpdateType::Move;
+                update.from = Some(from.clone());
+                moved_from.insert(from);
+            }
+        }
+        updates.retain(|update| {
+            update.update_type != FileUpdateType::Delete || !moved_from.contains(&update.file)
+        });
+
+        updates.extend(moves);
+        updates
+    }
+
+    /// Every file that exists after the deploy, as if all of them were created.
+    pub fn all_from_files(files: &HashMap<PathBuf, (FileState, FileMode)>) -> Vec<FileUpdate> {
+        files
+            .iter()
+            .filter(|(_, (_, mode))| !matches!(mode, FileMode::Deleted | FileMode::Ignored))
+            .map(|(path, (state, _))| FileUpdate {
+                file: path.clone(),
+                file_type: state.into(),
+                update_type: FileUpdateType::CreateOrUpdate,
+                state: state.clone(),
+                from: None,
+            })
+            .collect()
+    }
+
+    /// New path of `path` after this move, if it is the moved entry or lies below it.
+    pub fn moved_path(&self, path: &Path) -> Option<PathBuf> {
+        let relative = path.strip_prefix(self.from.as_ref()?).ok()?;
+
+        if relative.as_os_str().is_empty() {
+            Some(self.file.clone())
+        } else {
+            Some(self.file.join(relative))
+        }
+    }
+
+    /// Records a successfully applied update in the tracking information.
+    pub fn apply(&self, files_tracking: &mut FilesTracking) {
+        match self.update_type {
+            FileUpdateType::CreateOrUpdate => {
+                files_tracking
+                    .files
+                    .insert(self.file.clone(), self.state.clone());
+            }
+            FileUpdateType::Move => {
+                files_tracking.files = files_tracking
+                    .files
+                    .drain()
+                    .map(|(path, state)| (self.moved_path(&path).unwrap_or(path), state))
+                    .collect();
+                files_tracking
+                    .files
+                    .insert(self.file.clone(), self.state.clone());
+            }
+            FileUpdateType::Delete => {
+                files_tracking.files.remove(&self.file);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(hash: &str) -> FileState {
+        FileState::File(hash.repeat(64))
+    }
+
+    fn detect(files: &[(&str, FileState, FileMode)]) -> Vec<FileUpdate> {
+        let files: HashMap<PathBuf, (FileState, FileMode)> = files
+            .iter()
+            .map(|(path, state, mode)| (PathBuf::from(path), (state.clone(), *mode)))
+            .collect();
+        let protect = PathMatcher::new(".", &[]).unwrap();
+
+        FileUpdate::detect_moves(FileUpdate::from_files(&files, &protect), &files)
+            .into_iter()
+            .sorted()
+            .collect()
+    }
+
+    fn summary(updates: &[FileUpdate]) -> Vec<(FileUpdateType, &str, Option<&str>)> {
+        updates
+            .iter()
+            .map(|update| {
+                (
+                    update.update_type,
+                    update.file.to_str().unwrap(),
+                    update.from.as_deref().map(|from| from.to_str().unwrap()),
+                )
+            })
+            .collect()
+    }
+
+    fn moved(from: &str, to: &str) -> FileUpdate {
+        FileUpdate {
+            update_type: FileUpdateType::Move,
+            file_type: FileType::Directory,
+            file: PathBuf::from(to),
+            state: FileState::Directory,
+            from: Some(PathBuf::from(from)),
+        }
+    }
+
+    #[test]
+    fn renamed_file_is_moved() {
+        let updates = detect(&[
+            ("./a.txt", file("1"), FileMode::Deleted),
+            ("./b.txt", file("1"), FileMode::Created),
+        ]);
+
+        assert_eq!(
+            summary(&updates),
+            [(FileUpdateType::Move, "./b.txt", Some("./a.txt"))]
+        );
+    }
+
+    #[test]
+    fn renamed_directory_is_a_single_move() {
+        let updates = detect(&[
+            ("./old", FileState::Directory, FileMode::Deleted),
+            ("./old/x", file("1"), FileMode::Deleted),
+            ("./old/sub", FileState::Directory, FileMode::Deleted),
+            ("./old/sub/y", file("2"), FileMode::Deleted),
+            ("./new", FileState::Directory, FileMode::Created),
+            ("./new/x", file("1"), FileMode::Created),
+            ("./new/sub", FileState::Directory, FileMode::Created),
+            ("./new/sub/y", file("2"), FileMode::Created),
+        ]);
+
+        assert_eq!(
+            summary(&updates),
+            [(FileUpdateType::Move, "./new", Some("./old"))]
+        );
+    }
+
+    #[test]
+    fn duplicate_hashes_are_moved_once_each() {
+        let updates = detect(&[
+            ("./a", file("1"), FileMode::Deleted),
+            ("./b", file("1"), FileMode::Deleted),
+            ("./c", file("1"), FileMode::Created),
+            ("./d", file("1"), FileMode::Created),
+            ("./e", file("1"), FileMode::Created),
+        ]);
+
+        let sources: HashSet<_> = updates
+            .iter()
+            .filter(|update| update.update_type == FileUpdateType::Move)
+            .map(|update| update.from.clone().unwrap())
+            .collect();
+        assert_eq!(sources, HashSet::from(["./a".into(), "./b".into()]));
+        assert_eq!(
+            updates
+                .iter()
+                .filter(|update| update.update_type == FileUpdateType::CreateOrUpdate)
+                .count(),
+            1
+        );
+        assert!(
+            updates
+                .iter()
+                .all(|update| update.update_type != FileUpdateType::Delete)
+        );
+    }
+
+    #[test]
+    fn modified_file_is_not_moved() {
+        let updates = detect(&[
+            ("./a.txt", file("1"), FileMode::Deleted),
+            ("./b.txt", file("2"), FileMode::Created),
+        ]);
+
+        assert_eq!(
+            summary(&updates),
+            [
+                (FileUpdateType::CreateOrUpdate, "./b.txt", None),
+                (FileUpdateType::Delete, "./a.txt", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn updated_file_is_not_a_move_target() {
+        let updates = detect(&[
+            ("./a.txt", file("1"), FileMode::Deleted),
+            ("./b.txt", file("1"), FileMode::Updated),
+        ]);
+
+        assert_eq!(
+            summary(&updates),
+            [
+                (FileUpdateType::CreateOrUpdate, "./b.txt", None),
+                (FileUpdateType::Delete, "./a.txt", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn renamed_directory_with_a_modified_file_moves_the_rest() {
+        let updates = detect(&[
+            ("./old", FileState::Directory, FileMode::Deleted),
+            ("./old/x", file("1"), FileMode::Deleted),
+            ("./old/y", file("2"), FileMode::Deleted),
+            ("./new", FileState::Directory, FileMode::Created),
+            ("./new/x", file("1"), FileMode::Created),
+            ("./new/y", file("3"), FileMode::Created),
+        ]);
+
+        assert_eq!(
+            summary(&updates),
+            [
+                (FileUpdateType::CreateOrUpdate, "./new", None),
+                (FileUpdateType::CreateOrUpdate, "./new/y", None),
+                (FileUpdateType::Move, "./new/x", Some("./old/x")),
+                (FileUpdateType::Delete, "./old/y", None),
+                (FileUpdateType::Delete, "./old", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn moved_path_follows_the_moved_directory() {
+        let update = moved("./old", "./new");
+
+        assert_eq!(
+            update.moved_path(Path::new("./old")),
+            Some(PathBuf::from("./new"))
+        );
+        assert_eq!(
+            update.moved_path(Path::new("./old/sub/y")),
+            Some(PathBuf::from("./new/sub/y"))
+        );
+        assert_eq!(update.moved_path(Path::new("./older/x")), None);
+        assert_eq!(
+            FileUpdate {
+                from: None,
+                ..update
+            }
+            .moved_path(Path::new("./old")),
+            None
+        );
+    }
+
+    #[test]
+    fn applied_directory_move_renames_the_tracked_contents() {
+        let mut files_tracking = FilesTracking {
+            files: HashMap::from([
+                ("./old".into(), FileState::Directory),
+                ("./old/x".into(), file("1")),
+                ("./other".into(), file("2")),
+            ]),
+        };
+
+        moved("./old", "./new").apply(&mut files_tracking);
+
+        assert_eq!(
+            files_tracking.files,
+            HashMap::from([
+                ("./new".into(), FileState::Directory),
+                ("./new/x".into(), file("1")),
+                ("./other".into(), file("2")),
+            ])
+        );
+    }
+}
@@ -0,0 +1,450 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time,
+};
+
+use ignore::WalkBuilder;
+use itertools::Itertools;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::IgnoredPolicy,
+    prompt::confirm,
+    tracking::{FileState, FilesTracking, IGNORE_FILE_NAME, PathMatcher},
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FileMode {
+    Untouched,
+    Created,
+    Updated,
+    Deleted,
+    /// Tracked, but excluded by an ignore file since the last deploy
+    Ignored,
+}
+
+#[derive(Clone)]
+pub struct FileWalk {
+    files: Arc<RwLock<HashMap<PathBuf, (FileState, FileMode)>>>,
+}
+
+impl FileWalk {
+    fn insert_update(&self, path: PathBuf, state: FileState, mode: FileMode) {
+        self.files
+            .write()
+            .unwrap()
+            .insert(path.clone(), (state, mode));
+    }
+
+    fn update(&self, path: impl AsRef<Path>, state: FileState, force: bool) {
+        if self.files.read().unwrap().contains_key(path.as_ref()) {
+            let mode = if force || self.files.read().unwrap().get(path.as_ref()).unwrap().0 != state
+            {
+                FileMode::Updated
+            } else {
+                FileMode::Untouched
+            };
+
+            self.insert_update(path.as_ref().to_path_buf(), state, mode);
+        } else {
+            self.insert_update(path.as_ref().to_path_buf(), state, FileMode::Created);
+        }
+    }
+}
+
+impl FileWalk {
+    fn mode(&self, path: impl AsRef<Path>) -> Option<FileMode> {
+        self.files
+            .read()
+            .unwrap()
+            .get(path.as_ref())
+            .map(|(_, mode)| *mode)
+    }
+
+    fn into_files(self) -> HashMap<PathBuf, (FileState, FileMode)> {
+        Arc::try_unwrap(self.files).unwrap().into_inner().unwrap()
+    }
+}
+
+impl From<FilesTracking> for FileWalk {
+    fn from(value: FilesTracking) -> Self {
+        Self {
+            files: Arc::new(RwLock::new(
+                value
+                    .files
+                    .into_iter()
+                    .map(|(key, value)| (key, (value, FileMode::Deleted)))
+                    .collect(),
+            )),
+        }
+    }
+}
+
+/// Walks `base_path` in parallel, hashes every file and compares it against the tracking
+/// information. Tracked files that were not found are marked as deleted, or as ignored if they
+/// still exist but are excluded by an ignore file now. With `only`, files outside of the
+/// matching paths are neither hashed nor changed.
+pub fn collect_files(
+    base_path: &Path,
+    files_tracking: FilesTracking,
+    jobs: usize,
+    force: bool,
+    only: Option<&PathMatcher>,
+) -> Result<HashMap<PathBuf, (FileState, FileMode)>, Box<dyn std::error::Error>> {
+    let file_walk: FileWalk = files_tracking.into();
+
+    let mut walker = WalkBuilder::new(base_path);
+    walker
+        .add_custom_ignore_filename(IGNORE_FILE_NAME)
+        .hidden(false)
+        .threads(jobs);
+
+    if let Some(only) = only {
+        let only = only.clone();
+        // directories are still descended into, the selected files may lie below them
+        walker.filter_entry(move |entry| {
+            entry
+                .file_type()
+                .is_some_and(|file_type| file_type.is_dir())
+                || only.matched(entry.path(), false).is_some()
+        });
+    }
+
+    let walker = walker.build_parallel();
+
+    println!("[ftp-deploy] Collecting files using {} threads", jobs);
+    let start = time::Instant::now();
+
+    walker.run(|| {
+        let file_walk = file_walk.clone();
+
+        Box::new(move |result| {
+            let Ok(result) = result else {
+                return ignore::WalkState::Continue;
+            };
+
+            let path = result.path();
+            let state = if path.is_file() {
+                let mut hasher = Sha256::new();
+                let mut file = fs::File::open(path).unwrap();
+                io::copy(&mut file, &mut hasher).unwrap();
+
+                FileState::File(format!("{:x}", hasher.finalize()))
+            } else {
+                FileState::Directory
+            };
+
+            file_walk.update(path, state, force);
+
+            ignore::WalkState::Continue
+        })
+    });
+
+    let mut files = file_walk.into_files();
+
+    if let Some(only) = only {
+        restrict_files(&mut files, only);
+    }
+
+    for (path, (_, mode)) in files.iter_mut() {
+        if *mode == FileMode::Deleted && fs::symlink_metadata(path).is_ok() {
+            *mode = FileMode::Ignored;
+        }
+    }
+
+    println!("[ftp-deploy] Collecting files took {:?}.", start.elapsed(),);
+
+    Ok(files)
+}
+
+/// Compares files that are not found by walking a directory, like the entries of an archive or
+/// the files of a git tree, against the tracking information. The ignore file of `base_path`
+/// applies to their paths.
+pub(crate) struct EntryWalk<'a> {
+    base_path: &'a Path,
+    file_walk: FileWalk,
+    ignore: PathMatcher,
+    only: Option<&'a PathMatcher>,
+    force: bool,
+    /// Paths that were skipped because of the ignore file
+    ignored: HashSet<PathBuf>,
+    /// Paths that were compared already, directories are seen once for every file in them
+    seen: HashSet<PathBuf>,
+}
+
+impl<'a> EntryWalk<'a> {
+    pub(crate) fn new(
+        base_path: &'a Path,
+        files_tracking: FilesTracking,
+        force: bool,
+        only: Option<&'a PathMatcher>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut walk = Self {
+            base_path,
+            file_walk: files_tracking.into(),
+            ignore: PathMatcher::from_file(base_path, base_path.join(IGNORE_FILE_NAME))?,
+            only,
+            force,
+            ignored: HashSet::new(),
+            seen: HashSet::new(),
+        };
+        walk.directory(base_path);
+
+        Ok(walk)
+    }
+
+    pub(crate) fn directory(&mut self, path: &Path) {
+        if self.ignore.matched(path, true).is_some() {
+            self.ignored.insert(path.to_path_buf());
+            return;
+        }
+
+        if self.seen.insert(path.to_path_buf()) {
+            self.file_walk
+                .update(path, FileState::Directory, self.force);
+        }
+    }
+
+    /// Compares a file, returning whether it has to be uploaded.
+    pub(crate) fn file(&mut self, path: PathBuf, state: FileState) -> bool {
+        if self.ignore.matched(&path, false).is_some() {
+            self.ignored.insert(path);
+            return false;
+        }
+        if self
+            .only
+            .is_some_and(|only| only.matched(&path, false).is_none())
+        {
+            return false;
+        }
+
+        if !self.seen.insert(path.clone()) {
+            println!("[ftp-deploy] Skipping duplicate entry {}", path.display());
+            return false;
+        }
+
+        // only the files are listed, their directories are created anyway
+        for directory in path.ancestors().skip(1) {
+            self.directory(directory);
+            if directory == self.base_path {
+                break;
+            }
+        }
+
+        self.file_walk.update(&path, state, self.force);
+        self.file_walk.mode(&path) != Some(FileMode::Untouched)
+    }
+
+    /// The changes of all compared files, tracked files that were not seen are deleted unless
+    /// they were skipped because of the ignore file.
+    pub(crate) fn finish(self) -> HashMap<PathBuf, (FileState, FileMode)> {
+        let mut files = self.file_walk.into_files();
+
+        if let Some(only) = self.only {
+            restrict_files(&mut files, only);
+        }
+
+        for (path, (_, mode)) in files.iter_mut() {
+            if *mode == FileMode::Deleted && self.ignored.contains(path) {
+                *mode = FileMode::Ignored;
+            }
+        }
+
+        files
+    }
+}
+
+/// Leaves every file outside of `only` untouched, apart from the directories leading to the
+/// selected ones.
+fn restrict_files(files: &mut HashMap<PathBuf, (FileState, FileMode)>, only: &PathMatcher) {
+    let selected: HashSet<PathBuf> = files
+        .iter()
+        .filter(|(path, (state, _))| {
+            only.matched(path, matches!(state, FileState::Directory))
+                .is_some()
+        })
+        .map(|(path, _)| path.clone())
+        .collect();
+    // the directories leading to a selected path are created along with it
+    let ancestors: HashSet<&Path> = selected
+        .iter()
+        .flat_map(|path| path.ancestors().skip(1))
+        .collect();
+
+    for (path, (_, mode)) in files.iter_mut() {
+        if !selected.contains(path) && !ancestors.contains(path.as_path()) {
+            *mode = FileMode::Untouched;
+        }
+    }
+}
+
+/// Decides what happens to tracked files that became ignored. Files that are deleted on the
+/// server are marked as such, the others stay ignored and are kept on the server.
+pub fn apply_ignored_policy(
+    files: &mut HashMap<PathBuf, (FileState, FileMode)>,
+    policy: IgnoredPolicy,
+) {
+    let ignored = files
+        .iter_mut()
+        .filter(|(_, (_, mode))| *mode == FileMode::Ignored)
+        .sorted_by(|(a, _), (b, _)| a.cmp(b));
+
+    for (path, (_, mode)) in ignored {
+        let delete = match policy {
+            IgnoredPolicy::Keep => false,
+            IgnoredPolicy::Delete => true,
+            IgnoredPolicy::Ask => confirm(&format!(
+                "'{}' is ignored now, delete it from the server?",
+                path.display()
+            )),
+        };
+
+        if delete {
+            *mode = FileMode::Deleted;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GONE: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+    fn project(name: &str, files: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ftp-deploy-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for file in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, file).unwrap();
+        }
+
+        dir
+    }
+
+    fn tracking(base_path: &Path, files: &[&str]) -> FilesTracking {
+        FilesTracking {
+            files: files
+                .iter()
+                .map(|file| (base_path.join(file), FileState::File(GONE.to_string())))
+                .collect(),
+        }
+    }
+
+    fn mode(files: &HashMap<PathBuf, (FileState, FileMode)>, path: PathBuf) -> FileMode {
+        files[&path].1
+    }
+
+    #[test]
+    fn restriction_keeps_the_selection_and_its_parents() {
+        let mut files: HashMap<PathBuf, (FileState, FileMode)> = [
+            (".", FileState::Directory),
+            ("./a", FileState::Directory),
+            ("./a/b", FileState::Directory),
+            ("./a/b/kept.txt", FileState::File(GONE.to_string())),
+            ("./a/other.txt", FileState::File(GONE.to_string())),
+            ("./c", FileState::Directory),
+        ]
+        .into_iter()
+        .map(|(path, state)| (PathBuf::from(path), (state, FileMode::Created)))
+        .collect();
+        let only = PathMatcher::new(".", &[PathMatcher::literal_pattern("a/b/kept.txt")]).unwrap();
+
+        restrict_files(&mut files, &only);
+
+        let created: HashSet<&str> = files
+            .iter()
+            .filter(|(_, (_, mode))| *mode == FileMode::Created)
+            .map(|(path, _)| path.to_str().unwrap())
+            .collect();
+        assert_eq!(
+            created,
+            HashSet::from([".", "./a", "./a/b", "./a/b/kept.txt"])
+        );
+    }
+
+    #[test]
+    fn restricted_walk_keeps_deletions_in_the_selection() {
+        let base_path = project("restricted", &["a/kept.txt", "b/other.txt"]);
+        let tracked = tracking(
+            &base_path,
+            &["a/kept.txt", "a/gone.txt", "b/other.txt", "b/gone.txt"],
+        );
+        let only = PathMatcher::new(&base_path, &["/a".to_string()]).unwrap();
+
+        let files = collect_files(&base_path, tracked, 1, false, Some(&only)).unwrap();
+        fs::remove_dir_all(&base_path).unwrap();
+
+        assert_eq!(
+            mode(&files, base_path.join("a/kept.txt")),
+            FileMode::Updated
+        );
+        assert_eq!(
+            mode(&files, base_path.join("a/gone.txt")),
+            FileMode::Deleted
+        );
+        // not walked, neither deleted nor ignored
+        assert_eq!(
+            mode(&files, base_path.join("b/other.txt")),
+            FileMode::Untouched
+        );
+        assert_eq!(
+            mode(&files, base_path.join("b/gone.txt")),
+            FileMode::Untouched
+        );
+    }
+
+    #[test]
+    fn partial_walk_keeps_deletions_of_literal_paths() {
+        let base_path = project("partial", &["a/kept.txt", "a/other.txt"]);
+        let tracked = tracking(&base_path, &["a/kept.txt", "a/other.txt", "a/gone.txt"]);
+        let only = PathMatcher::new(
+            &base_path,
+            &[
+                PathMatcher::literal_pattern("a/kept.txt"),
+                PathMatcher::literal_pattern("a/gone.txt"),
+            ],
+        )
+        .unwrap();
+
+        let files = collect_files(&base_path, tracked, 1, false, Some(&only)).unwrap();
+        fs::remove_dir_all(&base_path).unwrap();
+
+        assert_eq!(
+            mode(&files, base_path.join("a/kept.txt")),
+            FileMode::Updated
+        );
+        assert_eq!(
+            mode(&files, base_path.join("a/gone.txt")),
+            FileMode::Deleted
+        );
+        assert_eq!(
+            mode(&files, base_path.join("a/other.txt")),
+            FileMode::Untouched
+        );
+    }
+
+    #[test]
+    fn ignored_files_that_exist_are_not_deleted() {
+        let base_path = project("ignored", &["a/secret.txt", IGNORE_FILE_NAME]);
+        fs::write(base_path.join(IGNORE_FILE_NAME), "secret.txt\n").unwrap();
+        let tracked = tracking(&base_path, &["a/secret.txt", "a/gone.txt"]);
+        let only = PathMatcher::new(&base_path, &["/a".to_string()]).unwrap();
+
+        let files = collect_files(&base_path, tracked, 1, false, Some(&only)).unwrap();
+        fs::remove_dir_all(&base_path).unwrap();
+
+        assert_eq!(
+            mode(&files, base_path.join("a/secret.txt")),
+            FileMode::Ignored
+        );
+        assert_eq!(
+            mode(&files, base_path.join("a/gone.txt")),
+            FileMode::Deleted
+        );
+    }
+}
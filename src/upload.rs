@@ -0,0 +1,732 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use ftp::{FtpStream, types::FtpError};
+use indicatif::{ProgressBar, ProgressStyle};
+use itertools::Itertools;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::{BackupMode, FtpCreds},
+    ftp::FtpStreamExt,
+    prompt::confirm,
+    tracking::{
+        BackupSet, FileState, FileType, FileUpdate, FileUpdateType, FilesTracking, RemoteTracking,
+    },
+};
+
+/// Suffix of the temporary names files are uploaded under before being renamed into place.
+const TEMP_SUFFIX: &str = ".ftp-deploy-tmp";
+
+/// Temporary name `file_name` is uploaded under, hidden and recognizable as left over.
+fn temp_name(file_name: &str) -> String {
+    format!(".{}{}", file_name, TEMP_SUFFIX)
+}
+
+/// Checks a file uploaded under a temporary name before it replaces the live one: the server
+/// must report the size that was sent, and the contents sent must be the ones `state` was
+/// collected from.
+fn check_upload(
+    sent: usize,
+    size: Option<usize>,
+    uploaded: &FileState,
+    state: &FileState,
+) -> Result<(), FtpError> {
+    if size != Some(sent) {
+        return Err(FtpError::InvalidResponse(format!(
+            "uploaded {} bytes but the server reports {:?}",
+            sent, size
+        )));
+    }
+
+    if uploaded != state {
+        return Err(FtpError::InvalidResponse(
+            "the file changed since it was collected, not replacing the live file".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Counts and hashes the bytes read, to compare them with the size of the uploaded file and
+/// the digest the file was collected with.
+pub(crate) struct HashingReader<R> {
+    inner: R,
+    count: usize,
+    hasher: Sha256,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self {
+            inner,
+            count: 0,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// SHA-256 digest of the contents that were read.
+    pub(crate) fn hash(self) -> String {
+        format!("{:x}", self.hasher.finalize())
+    }
+
+    /// State of the contents that were read.
+    pub(crate) fn state(self) -> FileState {
+        FileState::File(self.hash())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count += read;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// Receives the contents of a file, or the error reading it, from `FileSource::read_each`.
+pub type ContentsFn<'a> = dyn FnMut(&Path, &FileState, io::Result<&mut dyn Read>) + 'a;
+
+/// Provides the contents of the files that are uploaded.
+pub trait FileSource {
+    fn open(&self, file: &Path, state: &FileState) -> io::Result<Box<dyn Read + '_>>;
+
+    /// Size of a file, if it is known.
+    fn size(&self, file: &Path, state: &FileState) -> Option<u64>;
+
+    /// Calls `f` with the contents of each of `files`. Sources that read many files faster in
+    /// one go than one by one override it.
+    fn read_each(&self, files: &[(&Path, &FileState)], f: &mut ContentsFn<'_>) {
+        for (file, state) in files {
+            match self.open(file, state) {
+                Ok(mut reader) => f(file, state, Ok(&mut reader)),
+                Err(err) => f(file, state, Err(err)),
+            }
+        }
+    }
+}
+
+/// Reads files from the local file system.
+pub struct LocalFiles;
+
+impl FileSource for LocalFiles {
+    fn open(&self, file: &Path, _state: &FileState) -> io::Result<Box<dyn Read + '_>> {
+        Ok(Box::new(File::open(file)?))
+    }
+
+    fn size(&self, file: &Path, _state: &FileState) -> Option<u64> {
+        fs::metadata(file).map(|metadata| metadata.len()).ok()
+    }
+}
+
+/// Location remote files are backed up to before they are overwritten or deleted.
+pub enum Backup {
+    Remote(String),
+    Local(PathBuf),
+}
+
+impl Backup {
+    pub fn new(
+        mode: BackupMode,
+        id: &str,
+        base_path: impl AsRef<Path>,
+        creds: &FtpCreds,
+    ) -> Option<Self> {
+        match mode {
+            BackupMode::None => None,
+            BackupMode::Remote => Some(Backup::Remote(BackupSet::remote_dir(creds, id))),
+            BackupMode::Local => Some(Backup::Local(BackupSet::local_dir(base_path, id))),
+        }
+    }
+}
+
+/// Outcome of an upload besides the updated tracking information.
+#[derive(Debug, Default)]
+pub struct UploadReport {
+    pub created: Vec<PathBuf>,
+    pub updated: Vec<PathBuf>,
+    pub deleted: Vec<PathBuf>,
+    pub moved: Vec<PathBuf>,
+    pub backed_up: Vec<PathBuf>,
+    pub failed: Vec<PathBuf>,
+    /// Bytes uploaded
+    pub bytes: u64,
+}
+
+pub struct Uploader<'a> {
+    pub creds: &'a FtpCreds,
+    pub source: &'a dyn FileSource,
+    /// Overwrite files that were changed on the server
+    pub force: bool,
+    pub backup: Option<Backup>,
+    /// Upload to a temporary name and rename it into place once complete
+    pub temp_upload: bool,
+}
+
+impl Uploader<'_> {
+    /// Backs up the remote version of a file, returning its backup location if it existed.
+    fn backup_file(
+        &self,
+        ftp_stream: &mut FtpStream,
+        backup: &Backup,
+        file: &Path,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let remote_path = self.creds.remote_path(file);
+        if ftp_stream.remote_state(&remote_path)?.is_none() {
+            return Ok(None);
+        }
+
+        match backup {
+            Backup::Remote(dir) => {
+                let destination = self.remote_backup_path(ftp_stream, dir, file)?;
+                ftp_stream.rename(&remote_path, &destination)?;
+                Ok(Some(destination))
+            }
+            Backup::Local(dir) => {
+                let destination = dir.join(self.creds.relative_remote_path(file));
+                fs::create_dir_all(destination.parent().unwrap_or(dir))?;
+                ftp_stream.download(&remote_path, &destination)?;
+                Ok(Some(destination.display().to_string()))
+            }
+        }
+    }
+
+    /// Path a file is moved to when it is backed up on the server, creating its directory.
+    fn remote_backup_path(
+        &self,
+        ftp_stream: &mut FtpStream,
+        dir: &str,
+        file: &Path,
+    ) -> Result<String, FtpError> {
+        let destination = format!("{}/{}", dir, self.creds.relative_remote_path(file));
+        ftp_stream.cwd_or_create_recursive(Path::new(&destination).parent())?;
+
+        Ok(destination)
+    }
+
+    /// Uploads a file into the current directory, returning the number of bytes sent and the
+    /// state of the contents that were sent. With temporary uploads or a `backup` path the file
+    /// only replaces the live one after the transfer finished, its size was verified and the
+    /// contents are still the ones `state` was collected from. The live file is moved to
+    /// `backup` right before that, so it stays in place during the transfer.
+    fn put_file(
+        &self,
+        ftp_stream: &mut FtpStream,
+        file_name: &str,
+        reader: impl Read,
+        state: &FileState,
+        backup: Option<&str>,
+    ) -> Result<(usize, FileState), FtpError> {
+        let mut reader = HashingReader::new(reader);
+
+        if !self.temp_upload && backup.is_none() {
+            ftp_stream.put(file_name, &mut reader)?;
+            return Ok((reader.count, reader.state()));
+        }
+
+        let temp = temp_name(file_name);
+        ftp_stream.put(&temp, &mut reader)?;
+
+        let size = ftp_stream.size(&temp)?;
+        let count = reader.count;
+        let uploaded = reader.state();
+        if let Err(err) = check_upload(count, size, &uploaded, state) {
+            let _ = ftp_stream.rm(&temp);
+            return Err(err);
+        }
+
+        if let Some(backup) = backup {
+            if let Err(err) = ftp_stream.rename(file_name, backup) {
+                let _ = ftp_stream.rm(&temp);
+                return Err(err);
+            }
+
+            if let Err(err) = ftp_stream.rename(&temp, file_name) {
+                if ftp_stream.rename(backup, file_name).is_err() {
+                    return Err(FtpError::InvalidResponse(format!(
+                        "failed to rename '{}' into place ({}), the previous version is left at '{}'",
+                        temp, err, backup
+                    )));
+                }
+                let _ = ftp_stream.rm(&temp);
+                return Err(err);
+            }
+        } else if let Err(err) = ftp_stream.rename_over(&temp, file_name) {
+            // the upload is kept if the previous version could not be put back either
+            if matches!(ftp_stream.remote_state(file_name), Ok(Some(_))) {
+                let _ = ftp_stream.rm(&temp);
+            }
+            return Err(err);
+        }
+
+        Ok((count, uploaded))
+    }
+
+    /// Uploads the contents of a file read from the source. A file that could not be read, e.g.
+    /// because it was deleted since it was collected, fails like a failed transfer.
+    fn upload_file(
+        &self,
+        ftp_stream: &mut FtpStream,
+        file_name: &str,
+        contents: io::Result<impl Read>,
+        state: &FileState,
+        backup: Option<&str>,
+    ) -> Result<(usize, Option<FileState>), FtpError> {
+        let reader = contents.map_err(FtpError::ConnectionError)?;
+
+        self.put_file(ftp_stream, file_name, reader, state, backup)
+            .map(|(bytes, uploaded)| (bytes, Some(uploaded)))
+    }
+
+    /// Removes temporary files left behind by interrupted uploads.
+    fn remove_temp_files(&self, ftp_stream: &mut FtpStream) -> Result<(), FtpError> {
+        let internal = self.creds.state_dir();
+
+        let leftovers = ftp_stream
+            .list_recursive(&self.creds.remote_path("."))?
+            .into_iter()
+            .filter(|entry| !entry.is_dir && !entry.is_within(&internal))
+            .filter(|entry| {
+                let name = entry.path.rsplit('/').next().unwrap_or_default();
+                name.starts_with('.') && name.ends_with(TEMP_SUFFIX)
+            })
+            .collect_vec();
+
+        for entry in leftovers {
+            println!(
+                "[ftp-deploy] Removing leftover temporary file '{}'",
+                entry.path
+            );
+            ftp_stream.rm(&entry.path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks the files about to be overwritten or deleted for changes made on the server since
+    /// the last deploy. Returns the files that must not be touched.
+    fn detect_drift(
+        &self,
+        ftp_stream: &mut FtpStream,
+        updated_files: &[FileUpdate],
+        remote_tracking: &RemoteTracking,
+    ) -> Result<HashSet<PathBuf>, Box<dyn std::error::Error>> {
+        let mut drifted = Vec::new();
+
+        for update in updated_files
+            .iter()
+            .filter(|update| update.file_type == FileType::File)
+        {
+            // a moved file is taken from its previous location
+            let remote_file = update.from.as_ref().unwrap_or(&update.file);
+
+            let Some(recorded) = remote_tracking.files.get(remote_file) else {
+                continue;
+            };
+
+            let Some(current) = ftp_stream.remote_state(&self.creds.remote_path(remote_file))?
+            else {
+                continue;
+            };
+
+            if !current.matches(recorded) {
+                drifted.push(update);
+            }
+        }
+
+        if drifted.is_empty() {
+            return Ok(HashSet::new());
+        }
+
+        println!(
+            "[ftp-deploy] {} file(s) were changed on the server since the last deploy:",
+            drifted.len()
+        );
+        for update in &drifted {
+            println!("[ftp-deploy]   {}", update.file.display());
+        }
+
+        if self.force {
+            println!("[ftp-deploy] Overwriting remote changes because --force was given.");
+            return Ok(HashSet::new());
+        }
+
+        let refused: HashSet<PathBuf> = drifted
+            .into_iter()
+            .filter(|update| {
+                !confirm(&format!(
+                    "{} '{}' anyway?",
+                    update.update_type.get_verb(),
+                    update.file.display()
+                ))
+            })
+            .map(|update| update.file.clone())
+            .collect();
+
+        if !refused.is_empty() {
+            println!(
+                "[ftp-deploy] Skipping {} remotely changed file(s), use --force to overwrite them.",
+                refused.len()
+            );
+        }
+
+        Ok(refused)
+    }
+
+    /// Applies the updates on the server. Every update that succeeded is recorded in the
+    /// tracking information and the report, failed or skipped ones are left untouched. The
+    /// report also covers the updates made before an error aborted the upload.
+    pub fn upload_files(
+        &self,
+        updated_files: Vec<FileUpdate>,
+        files_tracking: &mut FilesTracking,
+        remote_tracking: &mut RemoteTracking,
+        report: &mut UploadReport,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        println!("[ftp-deploy] Uploading files to {}", self.creds.server);
+
+        let mut ftp_stream = self.creds.open_stream()?;
+
+        let drifted = self.detect_drift(&mut ftp_stream, &updated_files, remote_tracking)?;
+        let updated_files = updated_files
+            .into_iter()
+            .filter(|update| !drifted.contains(&update.file))
+            .collect_vec();
+
+        if self.temp_upload || matches!(self.backup, Some(Backup::Remote(_))) {
+            self.remove_temp_files(&mut ftp_stream)?;
+        }
+
+        ftp_stream.cwd("/")?;
+        let mut _current_ftp_path = PathBuf::from("/");
+
+        let style = ProgressStyle::with_template(
+            "{spinner:.green} [{elapsed_precise}] {msg} [{wide_bar:.cyan/blue}] ({eta})",
+        )
+        .unwrap()
+        .progress_chars("#>-");
+        let pb = ProgressBar::new(updated_files.len() as u64).with_style(style);
+
+        // new and changed files are read from the source in one go, in the order it reads them
+        // fastest. Directories are created before and moves and deletes happen after them.
+        let (uploads, updates): (Vec<_>, Vec<_>) =
+            updated_files.into_iter().sorted().partition(|update| {
+                update.update_type == FileUpdateType::CreateOrUpdate
+                    && update.file_type == FileType::File
+            });
+        let (directories, updates): (Vec<_>, Vec<_>) = updates
+            .into_iter()
+            .partition(|update| update.update_type == FileUpdateType::CreateOrUpdate);
+
+        for update in &directories {
+            pb.set_message(update.file.display().to_string());
+            self.apply_update(
+                &mut ftp_stream,
+                update,
+                None,
+                files_tracking,
+                remote_tracking,
+                report,
+            )?;
+            pb.inc(1);
+        }
+
+        let by_file: HashMap<&Path, &FileUpdate> = uploads
+            .iter()
+            .map(|update| (update.file.as_path(), update))
+            .collect();
+        let files = uploads
+            .iter()
+            .map(|update| (update.file.as_path(), &update.state))
+            .collect_vec();
+        let mut aborted = None;
+        self.source.read_each(&files, &mut |file, _, contents| {
+            if aborted.is_some() {
+                return;
+            }
+
+            pb.set_message(file.display().to_string());
+            if let Err(err) = self.apply_update(
+                &mut ftp_stream,
+                by_file[file],
+                Some(contents),
+                files_tracking,
+                remote_tracking,
+                report,
+            ) {
+                aborted = Some(err);
+            }
+            pb.inc(1);
+        });
+        if let Some(err) = aborted {
+            return Err(err);
+        }
+
+        for update in &updates {
+            pb.set_message(update.file.display().to_string());
+            self.apply_update(
+                &mut ftp_stream,
+                update,
+                None,
+                files_tracking,
+                remote_tracking,
+                report,
+            )?;
+            pb.inc(1);
+        }
+
+        Ok(())
+    }
+
+    /// Applies a single update on the server, recording it in the tracking information and the
+    /// report. `contents` are those of a new or changed file. A failed update is only reported,
+    /// an error means the upload cannot go on.
+    fn apply_update(
+        &self,
+        ftp_stream: &mut FtpStream,
+        update: &FileUpdate,
+        contents: Option<io::Result<&mut dyn Read>>,
+        files_tracking: &mut FilesTracking,
+        remote_tracking: &mut RemoteTracking,
+        report: &mut UploadReport,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let FileUpdate {
+            file,
+            file_type,
+            update_type,
+            ..
+        } = update;
+
+        // TODO: sort file paths and only do necessary mkdir's and cwd's
+
+        let Some(file_name) = file.file_name() else {
+            println!("[ftp-deploy] Skipping invalid file {}", file.display());
+            update.apply(files_tracking);
+            return Ok(());
+        };
+
+        let ftp_path = self.creds.ftp_path(file);
+        // TODO: get relative path to current path
+
+        let file_name: &str = file_name.try_into().unwrap();
+
+        let backup = match (&self.backup, file_type, update_type) {
+            // a replaced file is only moved into the remote backup once its replacement was
+            // uploaded completely
+            (Some(Backup::Remote(dir)), FileType::File, FileUpdateType::CreateOrUpdate) => {
+                match ftp_stream.remote_state(&self.creds.remote_path(file)) {
+                    Ok(Some(_)) => self
+                        .remote_backup_path(ftp_stream, dir, file)
+                        .map(|destination| (None, Some(destination)))
+                        .map_err(Into::into),
+                    Ok(None) => Ok((None, None)),
+                    Err(err) => Err(err.into()),
+                }
+            }
+            (Some(backup), FileType::File, _) if *update_type != FileUpdateType::Move => self
+                .backup_file(ftp_stream, backup, file)
+                .map(|backup| (backup, None)),
+            _ => Ok((None, None)),
+        };
+        let (backup, deferred_backup) = match backup {
+            Ok(backup) => backup,
+            Err::<_, Box<dyn std::error::Error>>(err) => {
+                println!(
+                    "[ftp-deploy] Failed to back up file '{}', leaving it untouched: {}",
+                    file.display(),
+                    err
+                );
+                report.failed.push(file.clone());
+                return Ok(());
+            }
+        };
+        let moved = backup.is_some() && matches!(self.backup, Some(Backup::Remote(_)));
+
+        ftp_stream.cwd_or_create_recursive(ftp_path.parent())?;
+
+        // TODO: update current path
+
+        let res = match update_type {
+            // moving the file into the backup already removed it
+            FileUpdateType::Delete if moved => Ok((0, None)),
+            FileUpdateType::Delete => match file_type {
+                FileType::File => ftp_stream.rm(file_name).map(|_| (0, None)),
+                FileType::Directory => ftp_stream.rmdir(file_name).map(|_| (0, None)),
+            },
+            FileUpdateType::Move => {
+                let from = self.creds.remote_path(update.from.as_ref().unwrap_or(file));
+                match ftp_stream.rename(&from, file_name) {
+                    Ok(()) => Ok((0, None)),
+                    Err(err) if *file_type == FileType::File => {
+                        println!(
+                            "[ftp-deploy] Failed to move '{}', uploading it instead: {}",
+                            file.display(),
+                            err
+                        );
+                        // the previous copy is only removed once the new one is in place
+                        self.upload_file(
+                            ftp_stream,
+                            file_name,
+                            self.source.open(file, &update.state),
+                            &update.state,
+                            None,
+                        )
+                        .inspect(|(_, uploaded)| {
+                            if uploaded.as_ref() == Some(&update.state)
+                                && let Err(err) = ftp_stream.rm(&from)
+                            {
+                                println!(
+                                    "[ftp-deploy] Failed to remove '{}' after uploading it to its new path: {}",
+                                    from, err
+                                );
+                            }
+                        })
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            FileUpdateType::CreateOrUpdate => match file_type {
+                FileType::Directory => ftp_stream.mkdir(file_name).map(|_| (0, None)),
+                FileType::File => self.upload_file(
+                    ftp_stream,
+                    file_name,
+                    contents.unwrap_or_else(|| Err(io::Error::other("the file was not read"))),
+                    &update.state,
+                    deferred_backup.as_deref(),
+                ),
+            },
+        };
+
+        match res {
+            // only possible without a temporary upload, the live file has the new contents
+            Ok((bytes, Some(uploaded))) if uploaded != update.state => {
+                println!(
+                    "[ftp-deploy] Failed to {} file '{}': it changed since it was collected, the next deploy uploads it again",
+                    update_type.get_verb(),
+                    file.display()
+                );
+                report.bytes += bytes as u64;
+                report.failed.push(file.clone());
+
+                // the new contents must not be mistaken for a change made on the server
+                if let Ok(Some(state)) = ftp_stream.remote_state(file_name) {
+                    remote_tracking.files.insert(file.clone(), state);
+                }
+            }
+            Ok((bytes, _)) => {
+                report.bytes += bytes as u64;
+                match update_type {
+                    FileUpdateType::Delete => report.deleted.push(file.clone()),
+                    FileUpdateType::Move => report.moved.push(file.clone()),
+                    _ if files_tracking.files.contains_key(file) => {
+                        report.updated.push(file.clone())
+                    }
+                    _ => report.created.push(file.clone()),
+                }
+
+                update.apply(files_tracking);
+
+                if backup.is_some() || deferred_backup.is_some() {
+                    report.backed_up.push(file.clone());
+                }
+
+                match (update_type, file_type) {
+                    (FileUpdateType::CreateOrUpdate, FileType::File) => {
+                        if let Ok(Some(state)) = ftp_stream.remote_state(file_name) {
+                            remote_tracking.files.insert(file.clone(), state);
+                        }
+                    }
+                    (FileUpdateType::Move, _) => {
+                        remote_tracking.files = remote_tracking
+                            .files
+                            .drain()
+                            .map(|(path, state)| (update.moved_path(&path).unwrap_or(path), state))
+                            .collect();
+                        if *file_type == FileType::File
+                            && let Ok(Some(state)) = ftp_stream.remote_state(file_name)
+                        {
+                            remote_tracking.files.insert(file.clone(), state);
+                        }
+                    }
+                    (FileUpdateType::Delete, _) => {
+                        remote_tracking.files.remove(file);
+                    }
+                    _ => {}
+                }
+            }
+            Err(err) => {
+                println!(
+                    "[ftp-deploy] Failed to {} file '{}': {}",
+                    update_type.get_verb(),
+                    file.display(),
+                    err
+                );
+                report.failed.push(file.clone());
+
+                if moved && let Some(backup) = &backup {
+                    // put the previous version back in place
+                    ftp_stream.rename(backup, &self.creds.remote_path(file))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state_of(contents: &[u8]) -> FileState {
+        FileState::File(format!("{:x}", Sha256::digest(contents)))
+    }
+
+    #[test]
+    fn hashes_what_was_read() {
+        let mut reader = HashingReader::new(&b"hello world"[..]);
+        io::copy(&mut reader, &mut io::sink()).unwrap();
+
+        assert_eq!(reader.count, 11);
+        assert_eq!(reader.state(), state_of(b"hello world"));
+    }
+
+    #[test]
+    fn hashes_only_what_was_read() {
+        let mut reader = HashingReader::new(&b"hello world"[..]);
+        let mut buf = [0; 5];
+        reader.read_exact(&mut buf).unwrap();
+
+        assert_eq!(reader.count, 5);
+        assert_eq!(reader.hash(), format!("{:x}", Sha256::digest(b"hello")));
+    }
+
+    #[test]
+    fn accepts_unchanged_uploads() {
+        let state = state_of(b"contents");
+
+        assert!(check_upload(8, Some(8), &state, &state).is_ok());
+    }
+
+    #[test]
+    fn rejects_files_changed_since_collected() {
+        let collected = state_of(b"contents");
+        let uploaded = state_of(b"changed!");
+
+        let err = check_upload(8, Some(8), &uploaded, &collected).unwrap_err();
+        assert!(err.to_string().contains("changed since it was collected"));
+    }
+
+    #[test]
+    fn rejects_incomplete_uploads() {
+        let state = state_of(b"contents");
+
+        assert!(check_upload(8, Some(4), &state, &state).is_err());
+        assert!(check_upload(8, None, &state, &state).is_err());
+    }
+}